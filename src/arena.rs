@@ -0,0 +1,126 @@
+// Storage for the objects of one kind
+//
+// Slots freed by erasure are recycled. Every slot has a generation, stored
+// in the references to its object and incremented when the object is
+// erased: a reference to an erased object never reaches the object that
+// later reuses its slot.
+
+use crate::valueref::{RawValueRef, GEN_MAX};
+
+struct Slot<T> {
+    gen: usize,
+    obj: Option<Box<T>>,
+}
+
+pub struct Arena<T> {
+    id: usize,
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> Arena<T> {
+    pub fn new(id: usize) -> Arena<T> {
+        Arena {
+            id,
+            slots: vec![],
+            free: vec![],
+        }
+    }
+
+    // Reference to the object added by the next call to `insert`
+    pub fn next_ref(&self) -> RawValueRef {
+        match self.free.last() {
+            Some(pos) => RawValueRef::make(self.id, self.slots[*pos].gen, *pos),
+            None => RawValueRef::make(self.id, 0, self.slots.len()),
+        }
+    }
+
+    pub fn insert(&mut self, obj: T) -> RawValueRef {
+        let res = self.next_ref();
+        match self.free.pop() {
+            Some(pos) => self.slots[pos].obj = Some(Box::new(obj)),
+            None => self.slots.push(Slot {
+                gen: 0,
+                obj: Some(Box::new(obj)),
+            }),
+        }
+        res
+    }
+
+    // None if the object was erased
+    pub fn get(&self, r: RawValueRef) -> Option<&T> {
+        let slot = self.slots.get(r.get_pos()).expect("invalid ref");
+        if slot.gen != r.get_gen() {
+            return None;
+        }
+        slot.obj.as_deref()
+    }
+
+    pub fn get_mut(&mut self, r: RawValueRef) -> Option<&mut T> {
+        let slot = self.slots.get_mut(r.get_pos()).expect("invalid ref");
+        if slot.gen != r.get_gen() {
+            return None;
+        }
+        slot.obj.as_deref_mut()
+    }
+
+    pub fn erase(&mut self, r: RawValueRef) {
+        let pos = r.get_pos();
+        let slot = self.slots.get_mut(pos).expect("invalid ref");
+        if slot.gen != r.get_gen() || slot.obj.is_none() {
+            panic!("Value already erased {:?}", r);
+        }
+
+        slot.obj = None;
+
+        // A slot whose generation can't be incremented anymore is never reused
+        if slot.gen < GEN_MAX {
+            slot.gen += 1;
+            self.free.push(pos);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|slot| slot.obj.as_deref())
+    }
+
+    // Number of slots, erased or not
+    #[allow(dead_code)]
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_recycle() {
+        let mut arena = Arena::new(1);
+        let a = arena.insert("a");
+        let b = arena.insert("b");
+        assert_eq!(arena.get(a), Some(&"a"));
+
+        arena.erase(a);
+        assert_eq!(arena.get(a), None);
+
+        let c = arena.insert("c");
+        assert_eq!(c.get_pos(), a.get_pos());
+        assert_eq!(c.get_gen(), a.get_gen() + 1);
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena.get(c), Some(&"c"));
+        assert_eq!(arena.get(b), Some(&"b"));
+        assert_eq!(arena.capacity(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Value already erased")]
+    fn arena_erase_stale() {
+        let mut arena = Arena::new(1);
+        let a = arena.insert("a");
+        arena.erase(a);
+        arena.insert("b");
+        arena.erase(a);
+    }
+}
@@ -1,15 +1,24 @@
+use crate::types::Type;
 use crate::value::Value;
-use crate::valueref::{ArgumentRef, BasicBlockRef, FunctionRef, InstructionRef, ValueRef};
+use crate::valueref::{ArgumentRef, FunctionRef};
 
 pub struct Argument {
     val: Value,
+    #[allow(dead_code)]
     arg_pos: usize,
+    #[allow(dead_code)]
     fun: FunctionRef,
+    ty: Type,
 }
 
 impl Argument {
     pub fn new(val: Value, arg_pos: usize, fun: FunctionRef) -> Argument {
-        Argument { val, arg_pos, fun }
+        Argument {
+            val,
+            arg_pos,
+            fun,
+            ty: Type::I64,
+        }
     }
 
     pub fn val(&self) -> &Value {
@@ -20,13 +29,23 @@ impl Argument {
         &mut self.val
     }
 
+    #[allow(dead_code)]
     pub fn arg_pos(&self) -> usize {
         self.arg_pos
     }
 
+    #[allow(dead_code)]
     pub fn fun(&self) -> FunctionRef {
         self.fun
     }
+
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    pub fn set_ty(&mut self, ty: Type) {
+        self.ty = ty;
+    }
 }
 
 impl From<&Argument> for ArgumentRef {
@@ -0,0 +1,96 @@
+// Function attributes, given with `.attr` after `.fun` or `.decl`
+//
+// - noreturn: calls never return to the caller
+// - nounwind: calls don't unwind the stack of the caller
+// - readnone: calls neither read nor write memory, calls without a used
+//   value can be removed
+// - pure: readnone and nounwind
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Attr {
+    NoReturn,
+    NoUnwind,
+    Pure,
+    ReadNone,
+}
+
+const ALL_ATTRS: [Attr; 4] = [Attr::NoReturn, Attr::NoUnwind, Attr::Pure, Attr::ReadNone];
+
+impl Attr {
+    pub fn parse(s: &str) -> Option<Attr> {
+        ALL_ATTRS.iter().copied().find(|attr| attr.name() == s)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Attr::NoReturn => "noreturn",
+            Attr::NoUnwind => "nounwind",
+            Attr::Pure => "pure",
+            Attr::ReadNone => "readnone",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+impl fmt::Display for Attr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Set of attributes of a function
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Attrs {
+    bits: u8,
+}
+
+impl Attrs {
+    #[allow(dead_code)]
+    pub fn new(attrs: &[Attr]) -> Attrs {
+        let mut res = Attrs::default();
+        for attr in attrs {
+            res.add(*attr);
+        }
+        res
+    }
+
+    pub fn has(&self, attr: Attr) -> bool {
+        self.bits & attr.bit() != 0
+    }
+
+    pub fn add(&mut self, attr: Attr) {
+        self.bits |= attr.bit();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Attr> + '_ {
+        ALL_ATTRS
+            .iter()
+            .copied()
+            .filter(move |attr| self.has(*attr))
+    }
+
+    pub fn is_noreturn(&self) -> bool {
+        self.has(Attr::NoReturn)
+    }
+
+    // Calls may read memory, written memory is a side effect
+    #[allow(dead_code)]
+    pub fn reads_memory(&self) -> bool {
+        !self.has(Attr::ReadNone) && !self.has(Attr::Pure)
+    }
+
+    // Calls can't be removed even if their value is unused: they may write
+    // memory, or never return
+    pub fn has_side_effects(&self) -> bool {
+        !(self.has(Attr::Pure) || self.has(Attr::ReadNone)) || self.is_noreturn()
+    }
+}
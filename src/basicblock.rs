@@ -1,10 +1,14 @@
+use crate::comments::Comments;
+use crate::debug_loc::DebugLoc;
 use crate::value::Value;
-use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRef};
+use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef};
 
 pub struct BasicBlock {
     val: Value,
     parent: Option<FunctionRef>,
     ins_list: Vec<InstructionRef>,
+    loc: Option<DebugLoc>,
+    comments: Comments,
 }
 
 impl BasicBlock {
@@ -13,6 +17,8 @@ impl BasicBlock {
             val,
             parent: None,
             ins_list: vec![],
+            loc: None,
+            comments: Comments::default(),
         }
     }
 
@@ -32,10 +38,29 @@ impl BasicBlock {
         &self.ins_list[..]
     }
 
+    #[allow(dead_code)]
     pub fn ins_mut(&mut self) -> &mut [InstructionRef] {
         &mut self.ins_list[..]
     }
 
+    pub fn loc(&self) -> Option<&DebugLoc> {
+        self.loc.as_ref()
+    }
+
+    pub fn set_loc(&mut self, loc: Option<DebugLoc>) {
+        self.loc = loc;
+    }
+
+    // Printed before the first instruction
+    pub fn comments(&self) -> &Comments {
+        &self.comments
+    }
+
+    #[allow(dead_code)]
+    pub fn comments_mut(&mut self) -> &mut Comments {
+        &mut self.comments
+    }
+
     pub fn parent(&self) -> Option<FunctionRef> {
         self.parent
     }
@@ -44,6 +69,7 @@ impl BasicBlock {
         self.parent = new_parent;
     }
 
+    #[allow(dead_code)]
     pub fn insert_begin(&mut self, ins: InstructionRef) {
         self.ins_list.insert(0, ins);
     }
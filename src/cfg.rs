@@ -1,13 +1,18 @@
+use crate::basicblock::BasicBlock;
 use crate::context::Context;
 use crate::digraph::Digraph;
 use crate::digraph_order;
+use crate::instruction::Instruction;
+use crate::isa::Opcode;
 use crate::valueref::{BasicBlockRef, FunctionRef, ValueRefEnum};
 use crate::vertex_adapter::VertexAdapter;
+use crate::visitor::{self, Visitor};
 
 pub struct CFG {
     fun: FunctionRef,
     va: VertexAdapter<BasicBlockRef>,
     g: Digraph,
+    exits: Vec<BasicBlockRef>,
 }
 
 impl CFG {
@@ -20,8 +25,9 @@ impl CFG {
             fun: fun.id(),
             va,
             g,
+            exits: vec![],
         };
-        res.prepare(ctx);
+        visitor::walk_fun(ctx, res.fun, &mut res);
         res
     }
 
@@ -41,6 +47,12 @@ impl CFG {
         self.g.succs(self.va.o2v(bb)).map(move |v| self.va.v2o(v))
     }
 
+    // Blocks leaving the function: ending with `ret`, or calling a noreturn function
+    #[allow(dead_code)]
+    pub fn exits(&self) -> &[BasicBlockRef] {
+        &self.exits[..]
+    }
+
     pub fn rev_postorder(&self) -> Vec<BasicBlockRef> {
         digraph_order::digraph_dfs(&self.g, digraph_order::DFSOrder::RevPost, 0, true)
             .iter()
@@ -52,18 +64,35 @@ impl CFG {
         self.g.save_tree(path).expect("Failed to write tree file");
     }
 
-    fn prepare(&mut self, ctx: &Context) {
-        let fun = self.fun.own(ctx).unwrap();
-        for bb in fun.bbs() {
-            let bb = bb.own(ctx).unwrap();
-            self.g
-                .set_label_vertex_name(self.va.o2v(bb.id()), bb.val().name());
-            let bins = bb.ins().iter().last().unwrap();
-            let bins = bins.own(ctx).unwrap();
-            for arg in bins.val().ops() {
-                if let ValueRefEnum::BB(target) = arg.to_enum() {
-                    self.g.add_edge(self.va.o2v(bb.id()), self.va.o2v(target));
-                }
+    fn add_exit(&mut self, bb: BasicBlockRef) {
+        if self.exits.last() != Some(&bb) {
+            self.exits.push(bb);
+        }
+    }
+}
+
+// Built by walking the blocks of the function
+impl Visitor for CFG {
+    fn visit_bb(&mut self, _ctx: &Context, bb: &BasicBlock) {
+        self.g
+            .set_label_vertex_name(self.va.o2v(bb.id()), bb.val().name());
+    }
+
+    fn visit_call(&mut self, ctx: &Context, ins: &Instruction) {
+        if ctx.ins_is_noreturn(ins.id()) {
+            self.add_exit(ins.parent().unwrap());
+        }
+    }
+
+    fn visit_term(&mut self, _ctx: &Context, ins: &Instruction) {
+        let bb = ins.parent().unwrap();
+        if ins.opcode() == Opcode::Ret {
+            self.add_exit(bb);
+        }
+
+        for arg in ins.val().ops() {
+            if let ValueRefEnum::BB(target) = arg.to_enum() {
+                self.g.add_edge(self.va.o2v(bb), self.va.o2v(target));
             }
         }
     }
@@ -74,15 +103,7 @@ mod tests {
     use super::*;
     use crate::gop;
     use crate::loader;
-
-    fn find_path(path: &str) -> String {
-        use std::path::Path;
-        Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join(path)
-            .to_str()
-            .unwrap()
-            .to_string()
-    }
+    use crate::test_utils::find_path;
 
     fn build_cfg(path: &str) -> CFG {
         let path = find_path(path);
@@ -108,6 +129,19 @@ mod tests {
         assert!(!g.has_edge(2, 2));
     }
 
+    #[test]
+    fn cfg_exits() {
+        // `fail` calls the noreturn `_std_exit`
+        let path = find_path("examples/attrs.ir");
+        let mut ctx = Context::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        let fun = ctx.funs().nth(3).unwrap();
+        let cfg = CFG::new(&ctx, fun);
+        let bbs = fun.own(&ctx).unwrap().bbs().to_vec();
+        assert_eq!(cfg.exits(), &[bbs[1], bbs[2]]);
+        assert!(cfg.graph().has_edge(1, 2));
+    }
+
     #[test]
     fn load_fact_rec() {
         let cfg = build_cfg("examples/fact_rec.ir");
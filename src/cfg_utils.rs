@@ -0,0 +1,393 @@
+// CFG transformations: loop preheaders and critical edge splitting
+//
+// Every new block ends with a single `b` to its successor. The branches of
+// its predecessors are retargeted to it, and the `@bb, value` pairs of the
+// phis of its successor are rewritten to name it as the incoming block.
+
+use crate::cfg::CFG;
+use crate::context::Context;
+use crate::dom_tree::DomTree;
+use crate::ir_builder::IrBuilder;
+use crate::isa::Opcode;
+use crate::names::NameAllocator;
+use crate::value::Use;
+use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRef};
+
+use std::collections::HashSet;
+
+// Split the edge `from -> to` with a new block inserted before `to`
+pub fn split_edge(ctx: &mut Context, from: BasicBlockRef, to: BasicBlockRef) -> BasicBlockRef {
+    let name = format!("{}.{}", bb_name(ctx, from), bb_name(ctx, to));
+    let bb = new_bb(ctx, to, &name);
+    retarget(ctx, from, to, bb);
+
+    for phi in phis(ctx, to) {
+        ctx.phi_retarget_incoming(phi, from, bb);
+    }
+
+    bb
+}
+
+// An edge is critical if it leaves a block with several successors and
+// enters a block with several predecessors
+// Returns the new blocks
+#[allow(dead_code)]
+pub fn split_critical_edges(ctx: &mut Context, fun: FunctionRef) -> Vec<BasicBlockRef> {
+    let cfg = CFG::new(ctx, fun);
+    let mut edges = vec![];
+    for from in fun.own(ctx).unwrap().bbs() {
+        if cfg.succs(*from).count() < 2 {
+            continue;
+        }
+        for to in cfg.succs(*from) {
+            if cfg.preds(to).count() > 1 {
+                edges.push((*from, to));
+            }
+        }
+    }
+
+    edges
+        .into_iter()
+        .map(|(from, to)| split_edge(ctx, from, to))
+        .collect()
+}
+
+// Returns the only predecessor of `header` outside of its loop, creating it
+// if needed
+// Incoming values of the header phis from several entry edges are merged
+// by new phis in the preheader
+pub fn insert_preheader(ctx: &mut Context, header: BasicBlockRef) -> BasicBlockRef {
+    let fun = header.own(ctx).unwrap().parent().unwrap();
+    let cfg = CFG::new(ctx, fun);
+    let dom = DomTree::new(ctx, &cfg, fun);
+    let name = format!("{}.pre", bb_name(ctx, header));
+
+    // The loop is entered from the function entry
+    // The checker rejects phis in the entry block, they have no value on entry
+    if header == dom.root() {
+        assert!(
+            phis(ctx, header).is_empty(),
+            "Phi in the entry block {}",
+            bb_name(ctx, header)
+        );
+        return new_bb(ctx, header, &name);
+    }
+
+    let entries: Vec<BasicBlockRef> = cfg
+        .preds(header)
+        .filter(|pred| !dom.dominates(header, *pred))
+        .collect();
+    assert!(!entries.is_empty(), "loop header has no entry edge");
+
+    if entries.len() == 1 {
+        if cfg.succs(entries[0]).count() == 1 {
+            return entries[0];
+        }
+        return split_edge(ctx, entries[0], header);
+    }
+
+    let pre = new_bb(ctx, header, &name);
+    for pred in &entries {
+        retarget(ctx, *pred, header, pre);
+    }
+
+    let term = *pre.own(ctx).unwrap().ins().last().unwrap();
+    let mut names = NameAllocator::of_values(ctx, fun);
+    for phi in phis(ctx, header) {
+        let phi_obj = phi.own(ctx).unwrap();
+        let outside: Vec<(BasicBlockRef, ValueRef)> = phi_obj
+            .as_phi()
+            .unwrap()
+            .incoming()
+            .filter(|(bb, _)| entries.contains(bb))
+            .collect();
+
+        let init = if outside.iter().all(|(_, val)| *val == outside[0].1) {
+            outside[0].1
+        } else {
+            let phi_name = format!("{}.pre", phi_obj.val().name());
+            let loc = phi_obj.loc().cloned();
+            let mut b = IrBuilder::with_names(ctx, &mut names);
+            b.set_loc(loc);
+            b.set_insert_before(term);
+            b.named(&phi_name).phi(&outside).into()
+        };
+
+        // The preheader takes the place of the first entry in the phi
+        ctx.phi_retarget_incoming(phi, outside[0].0, pre);
+        ctx.phi_set_incoming_value(phi, pre, init);
+        for (bb, _) in &outside[1..] {
+            ctx.phi_remove_incoming(phi, *bb);
+        }
+    }
+
+    pre
+}
+
+// Insert a preheader for every loop of `fun`
+// Loop headers are the targets of back edges
+// Returns the new blocks, existing blocks used as preheaders are not included
+pub fn insert_preheaders(ctx: &mut Context, fun: FunctionRef) -> Vec<BasicBlockRef> {
+    let cfg = CFG::new(ctx, fun);
+    let dom = DomTree::new(ctx, &cfg, fun);
+    let headers: Vec<BasicBlockRef> = cfg
+        .rev_postorder()
+        .into_iter()
+        .filter(|bb| cfg.preds(*bb).any(|pred| dom.dominates(*bb, pred)))
+        .collect();
+
+    let old_bbs: HashSet<BasicBlockRef> = fun.own(ctx).unwrap().bbs().iter().copied().collect();
+    headers
+        .into_iter()
+        .map(|header| insert_preheader(ctx, header))
+        .filter(|pre| !old_bbs.contains(pre))
+        .collect()
+}
+
+// Undo `insert_preheader` if nothing was hoisted into `pre`, a new block
+// holding only its `b` and the phis merging the values of the entry edges
+// The phis of the header take the values from the entries again
+// Returns true if `pre` was removed
+pub fn remove_preheader(ctx: &mut Context, pre: BasicBlockRef) -> bool {
+    let pre_obj = pre.own(ctx).unwrap();
+    let fun = pre_obj.parent().unwrap();
+    let (term, merges) = pre_obj.ins().split_last().unwrap();
+    let (term, merges) = (*term, merges.to_vec());
+    let header: BasicBlockRef = term.own(ctx).unwrap().val().ops()[0].raw().into();
+
+    // The merged values must only be used by the header phis
+    let header_phis = phis(ctx, header);
+    for merge in &merges {
+        let merge_obj = merge.own(ctx).unwrap();
+        if merge_obj.opcode() != Opcode::Phi
+            || merge_obj
+                .val()
+                .users()
+                .any(|user| !header_phis.iter().any(|phi| ValueRef::from(*phi) == user))
+        {
+            return false;
+        }
+    }
+
+    let cfg = CFG::new(ctx, fun);
+    let entries: Vec<BasicBlockRef> = cfg.preds(pre).collect();
+    for phi in &header_phis {
+        let val = phi.own(ctx).unwrap().as_phi().unwrap().incoming_value(pre);
+        let val = val.expect("header phi without value from the preheader");
+        if let [entry] = entries[..] {
+            ctx.phi_retarget_incoming(*phi, pre, entry);
+            continue;
+        }
+
+        // The entries take the place of the preheader in the phi
+        let mut incoming = vec![];
+        for entry in &entries {
+            let entry_val = match merges.iter().find(|merge| ValueRef::from(**merge) == val) {
+                Some(merge) => merge
+                    .own(ctx)
+                    .unwrap()
+                    .as_phi()
+                    .unwrap()
+                    .incoming_value(*entry),
+                None => Some(val),
+            };
+            incoming.push((*entry).into());
+            incoming.push(entry_val.unwrap());
+        }
+        let phi_obj = phi.own(ctx).unwrap();
+        let idx = phi_obj.as_phi().unwrap().value_idx(pre).unwrap();
+        let mut ops = phi_obj.val().ops().to_vec();
+        ops.splice(idx - 1..=idx, incoming);
+        ctx.ins_set_ops(*phi, &ops);
+    }
+
+    for entry in &entries {
+        retarget(ctx, *entry, pre, header);
+    }
+    let pre_ins = pre.own(ctx).unwrap().ins().to_vec();
+    ctx.erase_ins_group(&pre_ins);
+    ctx.bb_detach(pre);
+    ctx.erase_bb(pre);
+    true
+}
+
+// New block `b @succ`, inserted before `succ`
+// It is located at `succ`
+fn new_bb(ctx: &mut Context, succ: BasicBlockRef, name: &str) -> BasicBlockRef {
+    let succ_obj = succ.own(ctx).unwrap();
+    let fun = succ_obj.parent().unwrap();
+    let loc = succ_obj.loc().cloned();
+    let name = NameAllocator::of_blocks(ctx, fun).fresh(name);
+    let bb = ctx.make_bb(&name);
+    bb.own_mut(ctx).unwrap().set_loc(loc.clone());
+    let mut b = IrBuilder::new(ctx);
+    b.set_loc(loc);
+    b.set_insert_end(bb);
+    b.br(succ);
+    ctx.bb_insert_before(bb, succ);
+    bb
+}
+
+// Rewrite the targets `old` of the terminator of `bb` to `new`
+fn retarget(ctx: &mut Context, bb: BasicBlockRef, old: BasicBlockRef, new: BasicBlockRef) {
+    let term = *bb.own(ctx).unwrap().ins().last().unwrap();
+    let uses: Vec<Use> = old
+        .own(ctx)
+        .unwrap()
+        .val()
+        .uses()
+        .filter(|u| u.user() == term.into())
+        .collect();
+    for u in uses {
+        ctx.ins_set_op(term, u.idx(), new.into());
+    }
+}
+
+fn phis(ctx: &Context, bb: BasicBlockRef) -> Vec<InstructionRef> {
+    bb.own(ctx)
+        .unwrap()
+        .ins()
+        .iter()
+        .filter(|ins| ins.own(ctx).unwrap().opcode() == Opcode::Phi)
+        .copied()
+        .collect()
+}
+
+fn bb_name(ctx: &Context, bb: BasicBlockRef) -> String {
+    bb.own(ctx).unwrap().val().name().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker;
+    use crate::gop;
+    use crate::loader;
+    use crate::test_utils::find_path;
+
+    fn load_file(path: &str) -> (Context, FunctionRef) {
+        let path = find_path(path);
+
+        let mut ctx = Context::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        let fun = ctx.funs().next().unwrap();
+        (ctx, fun)
+    }
+
+    fn find_bb(ctx: &Context, fun: FunctionRef, name: &str) -> BasicBlockRef {
+        *fun.own(ctx)
+            .unwrap()
+            .bbs()
+            .iter()
+            .find(|bb| bb.own(ctx).unwrap().val().name() == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn split_critical_fact_iter() {
+        let (mut ctx, fun) = load_file("examples/fact_iter.ir");
+        let res = split_critical_edges(&mut ctx, fun);
+        checker::check_code(&ctx);
+
+        // B0 -> end, B0 -> loop, loop -> end and loop -> loop
+        assert_eq!(res.len(), 4);
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("bc %c, @B0.end, @B0.loop"));
+        assert!(out.contains("phi %r, @B0.loop, 1, @loop.loop, %r1"));
+        assert!(out.contains("phi %res, @B0.end, 1, @loop.end, %r1"));
+        assert!(out.contains("B0.loop:\n\tb @loop\n"));
+
+        // Nothing left to split
+        assert!(split_critical_edges(&mut ctx, fun).is_empty());
+    }
+
+    #[test]
+    fn preheader_cycle1() {
+        let (mut ctx, fun) = load_file("examples/cycle1.ir");
+        let header = find_bb(&ctx, fun, "B1");
+        let b0 = find_bb(&ctx, fun, "B0");
+
+        // B0 only branches to the header
+        assert_eq!(insert_preheader(&mut ctx, header), b0);
+    }
+
+    #[test]
+    fn remove_preheader_fact_iter() {
+        let (mut ctx, fun) = load_file("examples/fact_iter.ir");
+        let before = format!("{}", loader::build_gop(&ctx));
+        let new_bbs = insert_preheaders(&mut ctx, fun);
+        assert_eq!(new_bbs.len(), 1);
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("phi %r, @B0.loop, 1, @loop, %r1"));
+
+        // The preheader is empty, the code is back as loaded
+        assert!(remove_preheader(&mut ctx, new_bbs[0]));
+        checker::check_code(&ctx);
+        assert_eq!(format!("{}", loader::build_gop(&ctx)), before);
+    }
+
+    #[test]
+    fn remove_preheader_multiple_entries() {
+        let (mut ctx, fun) = load_file("examples/entries.ir");
+        let header = find_bb(&ctx, fun, "loop");
+        let pre = insert_preheader(&mut ctx, header);
+        assert!(remove_preheader(&mut ctx, pre));
+        checker::check_code(&ctx);
+
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(!out.contains("loop.pre"));
+        assert!(out.contains("phi %i, @left, 0, @right, 1, @loop, %i1"));
+        assert!(out.contains("phi %s, @left, %n, @right, %n, @loop, %s1"));
+    }
+
+    #[test]
+    fn remove_preheader_hoisted() {
+        let (mut ctx, fun) = load_file("examples/fact_iter.ir");
+        let pre = insert_preheaders(&mut ctx, fun)[0];
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let y = ctx.make_ins("y", Opcode::Add, true, &[x, x]);
+        let term = *pre.own(&ctx).unwrap().ins().last().unwrap();
+        ctx.ins_insert_before(y, term);
+        assert!(!remove_preheader(&mut ctx, pre));
+    }
+
+    #[test]
+    fn preheader_entry_header() {
+        // The loop starts at the function entry, it has no phis
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "entry.ir",
+            "f:\n.fun void\n\nB0:\n\tcall %x, @g\n\tcmplt %c, %x, 0\n\tbc %c, @B0, @end\n\nend:\n\tret\n\ng:\n.fun i64\n\nB0:\n\tret 0\n",
+        );
+        loader::load_gop(&mut ctx, &gmod);
+        let fun = ctx.funs().next().unwrap();
+        let header = find_bb(&ctx, fun, "B0");
+        let pre = insert_preheader(&mut ctx, header);
+        checker::check_code(&ctx);
+        assert_eq!(fun.own(&ctx).unwrap().bbs()[0], pre);
+
+        assert!(remove_preheader(&mut ctx, pre));
+        checker::check_code(&ctx);
+        assert_eq!(fun.own(&ctx).unwrap().bbs()[0], header);
+    }
+
+    #[test]
+    fn preheader_multiple_entries() {
+        let (mut ctx, fun) = load_file("examples/entries.ir");
+        let header = find_bb(&ctx, fun, "loop");
+        let pre = insert_preheader(&mut ctx, header);
+        checker::check_code(&ctx);
+
+        assert_eq!(pre.own(&ctx).unwrap().val().name(), "loop.pre");
+        let cfg = CFG::new(&ctx, fun);
+        let preds: Vec<BasicBlockRef> = cfg.preds(header).collect();
+        assert_eq!(preds.len(), 2);
+        assert!(preds.contains(&pre));
+
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("phi %i.pre, @left, 0, @right, 1"));
+        assert!(out.contains("phi %i, @loop.pre, %i.pre, @loop, %i1"));
+        assert!(out.contains("phi %s, @loop.pre, %n, @loop, %s1"));
+        assert!(!out.contains("@left, %n"));
+    }
+}
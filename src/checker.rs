@@ -1,8 +1,11 @@
 use crate::cfg::CFG;
 use crate::context::Context;
+use crate::debug_loc::loc_prefix;
 use crate::dom_tree::DomTree;
-use crate::isa::ISA;
-use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRef, ValueRefEnum};
+use crate::instruction::Instruction;
+use crate::isa::Opcode;
+use crate::types::Type;
+use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRefEnum};
 
 use std::collections::HashSet;
 use std::hash::Hash;
@@ -49,7 +52,8 @@ where
     T: Eq + Hash,
 {
     fn drop(&mut self) {
-        assert!(self.stack.is_empty())
+        // Scopes are left open when a check fails
+        assert!(self.stack.is_empty() || std::thread::panicking())
     }
 }
 
@@ -59,6 +63,7 @@ struct Checker {
     vals: Option<ScopedSet<InstructionRef>>,
     cfg: Option<CFG>,
     dom: Option<DomTree>,
+    ret_ty: Option<Type>,
 }
 
 impl Checker {
@@ -69,6 +74,7 @@ impl Checker {
             vals: None,
             cfg: None,
             dom: None,
+            ret_ty: None,
         }
     }
 
@@ -91,22 +97,45 @@ impl Checker {
 
     fn init_fun(&mut self, ctx: &Context, fun: FunctionRef) {
         let fun = fun.own(ctx).unwrap();
-        self.bbs = fun.bbs().iter().map(|x| *x).collect();
+        self.bbs = fun.bbs().iter().copied().collect();
     }
 
     fn check_fun(&mut self, ctx: &Context, fun: FunctionRef) {
         let fun = fun.own(ctx).unwrap();
         if self.bbs.is_empty() {
-            panic!("Empty function {}", fun.val().name());
+            panic!(
+                "{}Empty function {}",
+                loc_prefix(fun.loc()),
+                fun.val().name()
+            );
         }
 
         self.vals = Some(ScopedSet::new());
+        self.ret_ty = Some(fun.ret_ty());
         self.cfg = Some(CFG::new(ctx, fun.id()));
         self.dom = Some(DomTree::new(ctx, self.cfg.as_ref().unwrap(), fun.id()));
-        self.check_bb(ctx, self.dom.as_ref().unwrap().root());
+        let entry = self.dom.as_ref().unwrap().root();
+        self.check_entry(ctx, entry);
+        self.check_bb(ctx, entry);
         self.dom = None;
         self.cfg = None;
         self.vals = None;
+        self.ret_ty = None;
+    }
+
+    // Phis have no value on function entry
+    fn check_entry(&self, ctx: &Context, entry: BasicBlockRef) {
+        for ins in entry.own(ctx).unwrap().ins() {
+            let ins = ins.own(ctx).unwrap();
+            if ins.opcode() == Opcode::Phi {
+                panic!(
+                    "{}Phi {} in the entry block of function {}",
+                    loc_prefix(ins.loc()),
+                    ins.val().name(),
+                    fun_name(ctx, ins)
+                );
+            }
+        }
     }
 
     fn check_bb(&mut self, ctx: &Context, bb: BasicBlockRef) {
@@ -115,7 +144,11 @@ impl Checker {
         let dom_succs: Vec<BasicBlockRef> = self.dom.as_ref().unwrap().succs(bb.id()).collect();
         let cfg_succs: Vec<BasicBlockRef> = self.cfg.as_ref().unwrap().succs(bb.id()).collect();
         if bb.ins().is_empty() {
-            panic!("Empty basic block {}", bb.val().name());
+            panic!(
+                "{}Empty basic block {}",
+                loc_prefix(bb.loc()),
+                bb.val().name()
+            );
         }
 
         for ins in bb.ins() {
@@ -139,13 +172,14 @@ impl Checker {
         let ins = ins.own(ctx).unwrap();
         let vals = self.vals.as_mut().unwrap();
 
-        if ins.opname() != "phi" {
+        if ins.opcode() != Opcode::Phi {
             //Phi operands are tested in CFG preds
             for op in ins.val().ops() {
                 if let ValueRefEnum::Ins(op_use) = op.to_enum() {
                     if !vals.contains(op_use) {
                         panic!(
-                            "Use before def of operand {}",
+                            "{}Use before def of operand {}",
+                            loc_prefix(ins.loc()),
                             op_use.own(ctx).unwrap().val().name()
                         );
                     }
@@ -156,36 +190,198 @@ impl Checker {
         if ins.val().is_def() {
             vals.put(ins.id());
         }
+
+        self.check_operands(ctx, ins.id());
+        self.check_types(ctx, ins.id());
+    }
+
+    fn check_operands(&self, ctx: &Context, ins: InstructionRef) {
+        let ins = ins.own(ctx).unwrap();
+        let infos = ins.infos();
+        if let Err(err) = infos.operands().check(ins.val().ops()) {
+            panic!(
+                "{}Bad operands of {} in function {}: {}",
+                loc_prefix(ins.loc()),
+                ins.opname(),
+                fun_name(ctx, ins),
+                err
+            );
+        }
+
+        if !infos.allows_def(ins.val().is_def()) {
+            panic!(
+                "{}Bad definition for instruction {}",
+                loc_prefix(ins.loc()),
+                ins.opname()
+            );
+        }
+    }
+
+    fn check_types(&self, ctx: &Context, ins: InstructionRef) {
+        let ins = ins.own(ctx).unwrap();
+        let ops = ins.val().ops();
+        let fun_name = || fun_name(ctx, ins);
+
+        match ins.opcode() {
+            Opcode::Ret => match (self.ret_ty.unwrap(), ops.len()) {
+                (Type::Void, 0) => {}
+                (Type::Void, _) => panic!(
+                    "{}ret with a value in void function {}",
+                    loc_prefix(ins.loc()),
+                    fun_name()
+                ),
+                (_, 0) => panic!(
+                    "{}ret without a value in non-void function {}",
+                    loc_prefix(ins.loc()),
+                    fun_name()
+                ),
+                _ => {}
+            },
+            Opcode::Bc | Opcode::Select => {
+                let ty = ctx.val_type(ops[0]);
+                if ty != Type::I1 {
+                    panic!(
+                        "{}{} on a non boolean value of type {} in function {}",
+                        loc_prefix(ins.loc()),
+                        ins.opname(),
+                        ty,
+                        fun_name()
+                    );
+                }
+            }
+            Opcode::Call if ins.val().is_def() && ins.ty() == Type::Void => {
+                panic!(
+                    "{}call %{} defines a value of type void in function {}",
+                    loc_prefix(ins.loc()),
+                    ins.val().name(),
+                    fun_name()
+                );
+            }
+            _ => {}
+        }
+
+        let expected: &[Type] = match ins.opcode() {
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Rem => {
+                &[Type::I64, Type::I64]
+            }
+            Opcode::Shl | Opcode::Shr => &[Type::I64, Type::I64],
+            Opcode::Neg | Opcode::Alloca => &[Type::I64],
+            Opcode::Offset => &[Type::Ptr, Type::I64],
+            Opcode::Load => &[Type::Ptr],
+            Opcode::Store => &[Type::I64, Type::Ptr],
+            _ => &[],
+        };
+        for (idx, ty) in expected.iter().enumerate() {
+            let op_ty = ctx.val_type(ops[idx]);
+            if op_ty != *ty {
+                panic!(
+                    "{}{} operand {} of type {}, expected {} in function {}",
+                    loc_prefix(ins.loc()),
+                    ins.opname(),
+                    idx,
+                    op_ty,
+                    ty,
+                    fun_name()
+                );
+            }
+        }
+
+        // Arguments of a call match the parameters of the callee
+        if let Some(ValueRefEnum::Fun(callee)) = match ins.opcode() {
+            Opcode::Call => Some(ops[0].to_enum()),
+            _ => None,
+        } {
+            let callee = callee.own(ctx).unwrap();
+            if callee.args().len() != ops.len() - 1 {
+                panic!(
+                    "{}call to @{} with {} arguments, expected {} in function {}",
+                    loc_prefix(ins.loc()),
+                    callee.val().name(),
+                    ops.len() - 1,
+                    callee.args().len(),
+                    fun_name()
+                );
+            }
+            for (idx, arg) in callee.args().iter().enumerate() {
+                let (ty, arg_ty) = (ctx.val_type(ops[idx + 1]), arg.own(ctx).unwrap().ty());
+                if ty != arg_ty {
+                    panic!(
+                        "{}call to @{} argument {} of type {}, expected {} in function {}",
+                        loc_prefix(ins.loc()),
+                        callee.val().name(),
+                        idx,
+                        ty,
+                        arg_ty,
+                        fun_name()
+                    );
+                }
+            }
+        }
+
+        let store_global = match ins.opcode() {
+            Opcode::Store => match ops[1].to_enum() {
+                ValueRefEnum::Global(g) => Some(g.own(ctx).unwrap()),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(g) = store_global {
+            if g.is_const() {
+                panic!(
+                    "{}store to constant global @{} in function {}",
+                    loc_prefix(ins.loc()),
+                    g.val().name(),
+                    fun_name()
+                );
+            }
+        }
+
+        // Both values of a select, and operands of bitwise operations
+        let same = match ins.opcode() {
+            Opcode::Select => Some((ops[1], ops[2])),
+            Opcode::And | Opcode::Or | Opcode::Xor => Some((ops[0], ops[1])),
+            _ => None,
+        };
+        if let Some((a, b)) = same {
+            let (ta, tb) = (ctx.val_type(a), ctx.val_type(b));
+            if ta != tb {
+                panic!(
+                    "{}{} on values of types {} and {} in function {}",
+                    loc_prefix(ins.loc()),
+                    ins.opname(),
+                    ta,
+                    tb,
+                    fun_name()
+                );
+            }
+        }
     }
 
     fn check_phis(&mut self, ctx: &Context, parent: BasicBlockRef, bb: BasicBlockRef) {
         let bb = bb.own(ctx).unwrap();
         let vals = self.vals.as_ref().unwrap();
-        let parent_val: ValueRef = parent.into();
 
         for ins in bb.ins() {
             let ins = ins.own(ctx).unwrap();
-            if ins.opname() != "phi" {
+            if ins.opcode() != Opcode::Phi {
                 break;
             }
 
-            let ops = ins.val().ops();
-            let mut op_pos = None;
-            for (idx, op) in ops.iter().enumerate() {
-                if *op == parent_val {
-                    op_pos = Some(idx);
-                }
-            }
-            let op_pos = op_pos.expect(&format!(
-                "Phi predecesor value for {} is missing in {}",
-                parent_val.own(ctx).unwrap().name(),
-                bb.val().name()
-            ));
-
-            if let ValueRefEnum::Ins(op_use) = ops[op_pos + 1].to_enum() {
+            let incoming = ins.as_phi().unwrap().incoming_value(parent);
+            let incoming = incoming.unwrap_or_else(|| {
+                panic!(
+                    "{}Phi predecesor value for {} is missing in {}",
+                    loc_prefix(ins.loc()),
+                    parent.own(ctx).unwrap().val().name(),
+                    bb.val().name()
+                )
+            });
+
+            if let ValueRefEnum::Ins(op_use) = incoming.to_enum() {
                 if !vals.contains(op_use) {
                     panic!(
-                        "Use before def in phi of operand {}",
+                        "{}Use before def in phi of operand {}",
+                        loc_prefix(ins.loc()),
                         op_use.own(ctx).unwrap().val().name()
                     );
                 }
@@ -197,12 +393,10 @@ impl Checker {
         let bb = bb.own(ctx).unwrap();
         let bins = bb.ins().last().unwrap();
         let bins = bins.own(ctx).unwrap();
-        let bins_infos = ISA::instance()
-            .find_ins(bins.opname())
-            .expect("Unknown last instruction of basic block");
-        if !bins_infos.is_term(&[]) {
+        if !bins.infos().is_term() {
             panic!(
-                "Last instruction of basic block {} is not a terminal",
+                "{}Last instruction of basic block {} is not a terminal",
+                loc_prefix(bins.loc()),
                 bb.val().name()
             );
         }
@@ -210,7 +404,8 @@ impl Checker {
         for succ in bins.targets_bbs() {
             if !self.bbs.contains(&succ) {
                 panic!(
-                    "terminator in basic block {} branch to foreign block {}",
+                    "{}terminator in basic block {} branch to foreign block {}",
+                    loc_prefix(bins.loc()),
                     bb.val().name(),
                     succ.own(ctx).unwrap().val().name()
                 );
@@ -222,3 +417,175 @@ impl Checker {
 pub fn check_code(ctx: &Context) {
     Checker::new().run(ctx)
 }
+
+// Name of the function containing `ins`
+fn fun_name(ctx: &Context, ins: &Instruction) -> String {
+    let bb = ins.parent().unwrap().own(ctx).unwrap();
+    let fun = bb.parent().unwrap().own(ctx).unwrap();
+    fun.val().name().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gop;
+    use crate::ir_builder::IrBuilder;
+    use crate::loader;
+    use crate::valueref::ValueRef;
+
+    // fun(%x) with a single block `entry`
+    fn make_fun(ctx: &mut Context, ret_ty: Type) -> (FunctionRef, BasicBlockRef) {
+        let fun = ctx.make_fun("f", 1, false);
+        fun.own_mut(ctx).unwrap().set_ret_ty(ret_ty);
+        ctx.fun_get_arg_mut(fun, 0).val_mut().rename("x");
+        let entry = ctx.make_bb("entry");
+        ctx.bb_insert_in(entry, fun);
+        (fun, entry)
+    }
+
+    #[test]
+    #[should_panic(expected = "ret with a value in void function f")]
+    fn check_ret_void() {
+        let mut ctx = Context::new();
+        let (_, entry) = make_fun(&mut ctx, Type::Void);
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        let zero = b.const_int(0);
+        b.ret(Some(zero));
+        check_code(&ctx);
+    }
+
+    #[test]
+    #[should_panic(expected = "bc on a non boolean value of type i64 in function f")]
+    fn check_bc_int() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::I64);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let exit = ctx.make_bb("exit");
+        ctx.bb_insert_in(exit, fun);
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        let y = b.add(x, x);
+        b.cond_br(y.into(), exit, exit);
+        b.set_insert_end(exit);
+        b.ret(Some(x));
+        check_code(&ctx);
+    }
+
+    #[test]
+    #[should_panic(expected = "Bad operands of b in function f: operand 0 is a value")]
+    fn check_operand_kind() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::I64);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let br = ctx.make_ins("", Opcode::B, false, &[x]);
+        ctx.ins_insert_in(br, entry);
+        check_code(&ctx);
+    }
+
+    #[test]
+    #[should_panic(expected = "select on a non boolean value of type i64 in function f")]
+    fn check_select_int() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::I64);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        let y = b.select(x, x, x);
+        b.ret(Some(y.into()));
+        check_code(&ctx);
+    }
+
+    #[test]
+    #[should_panic(expected = "load operand 0 of type i64, expected ptr in function f")]
+    fn check_load_int() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::I64);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        let y = b.load(x);
+        b.ret(Some(y.into()));
+        check_code(&ctx);
+    }
+
+    #[test]
+    fn check_memory() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::I64);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        let size = b.const_int(16);
+        let p = b.alloca(size);
+        let q = b.offset(p.into(), size);
+        b.store(x, q.into());
+        let y = b.load(q.into());
+        b.ret(Some(y.into()));
+        assert_eq!(ctx.val_type(p.into()), Type::Ptr);
+        check_code(&ctx);
+    }
+
+    #[test]
+    #[should_panic(expected = "call to @g with 2 arguments, expected 1 in function f")]
+    fn check_call_arity() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::I64);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let g = ctx.make_fun("g", 1, true);
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        let y = b.call(g, &[x, x]);
+        b.ret(Some(y.into()));
+        check_code(&ctx);
+    }
+
+    #[test]
+    #[should_panic(expected = "store to constant global @table in function f")]
+    fn check_store_const() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::Void);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let table = ctx.make_global("table", true, 4, &[1, 2]);
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        b.store(x, table.into());
+        b.ret(None);
+        check_code(&ctx);
+    }
+
+    #[test]
+    #[should_panic(expected = "entry.ir:5:2: Phi i in the entry block of function f")]
+    fn check_entry_phi() {
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "entry.ir",
+            "f:\n.fun i64, %x\n\nB0:\n\tphi %i, @B0, %i1\n\tadd %i1, %i, 1\n\tcmplt %c, %i1, %x\n\tbc %c, @B0, @end\n\nend:\n\tret %i1\n",
+        );
+        loader::load_gop(&mut ctx, &gmod);
+        check_code(&ctx);
+    }
+
+    #[test]
+    fn check_bc_bool() {
+        let mut ctx = Context::new();
+        let (fun, entry) = make_fun(&mut ctx, Type::I64);
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let exit = ctx.make_bb("exit");
+        ctx.bb_insert_in(exit, fun);
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        let c = b.cmplt(x, x);
+        b.cond_br(c.into(), exit, exit);
+        b.set_insert_end(exit);
+        b.ret(Some(x));
+        check_code(&ctx);
+    }
+}
@@ -0,0 +1,38 @@
+// Comments attached to a global, function, block or instruction
+// `pre` lines are printed before it, `eol` at the end of its line
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Comments {
+    pre: Vec<String>,
+    eol: String,
+}
+
+impl Comments {
+    pub fn new(pre: &[String], eol: &str) -> Comments {
+        Comments {
+            pre: pre.to_vec(),
+            eol: eol.to_string(),
+        }
+    }
+
+    pub fn pre(&self) -> &[String] {
+        &self.pre[..]
+    }
+
+    pub fn add_pre(&mut self, comm: String) {
+        self.pre.push(comm);
+    }
+
+    pub fn eol(&self) -> &str {
+        &self.eol
+    }
+
+    pub fn set_eol(&mut self, comm: String) {
+        self.eol = comm;
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.pre.is_empty() && self.eol.is_empty()
+    }
+}
@@ -1,5 +1,5 @@
 use crate::value::Value;
-use crate::valueref::{BasicBlockRef, ConstantRef, FunctionRef, InstructionRef, ValueRef};
+use crate::valueref::ConstantRef;
 
 pub struct Constant {
     val: Value,
@@ -1,176 +1,121 @@
+use crate::arena::Arena;
 use crate::argument::Argument;
+use crate::attrs::Attrs;
 use crate::basicblock::BasicBlock;
 use crate::constant::Constant;
 use crate::function::Function;
-use crate::indexable::Indexable;
+use crate::global::Global;
 use crate::instruction::Instruction;
-use crate::value::Value;
+use crate::isa::Opcode;
+use crate::types::Type;
+use crate::value::{Use, Value};
 use crate::valueref::{
-    ArgumentRef, BasicBlockRef, ConstantRef, FunctionRef, InstructionRef, RawValueRef, SubValueRef,
-    ValueRef,
+    ArgumentRef, BasicBlockRef, ConstantRef, FunctionRef, GlobalRef, InstructionRef, SubValueRef,
+    ValueRef, ValueRefEnum,
 };
 
 pub struct Context {
-    data_ins: Vec<Option<Box<Instruction>>>,
-    data_bbs: Vec<Option<Box<BasicBlock>>>,
-    data_args: Vec<Option<Box<Argument>>>,
-    data_funs: Vec<Option<Box<Function>>>,
-    data_consts: Vec<Option<Box<Constant>>>,
+    data_ins: Arena<Instruction>,
+    data_bbs: Arena<BasicBlock>,
+    data_args: Arena<Argument>,
+    data_funs: Arena<Function>,
+    data_consts: Arena<Constant>,
+    data_globals: Arena<Global>,
 }
 
 impl Context {
     pub fn new() -> Self {
         Self {
-            data_ins: vec![],
-            data_bbs: vec![],
-            data_args: vec![],
-            data_funs: vec![],
-            data_consts: vec![],
+            data_ins: Arena::new(InstructionRef::ID),
+            data_bbs: Arena::new(BasicBlockRef::ID),
+            data_args: Arena::new(ArgumentRef::ID),
+            data_funs: Arena::new(FunctionRef::ID),
+            data_consts: Arena::new(ConstantRef::ID),
+            data_globals: Arena::new(GlobalRef::ID),
         }
     }
 
     pub fn get_data_value(&self, r: ValueRef) -> Option<&Value> {
-        let raw = RawValueRef::new(r.to_index());
-        let (id, pos) = (raw.get_id(), raw.get_pos());
-        match id {
-            InstructionRef::ID => match self.data_ins.get(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val()),
-                None => None,
-            },
-            BasicBlockRef::ID => match self.data_bbs.get(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val()),
-                None => None,
-            },
-            ArgumentRef::ID => match self.data_args.get(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val()),
-                None => None,
-            },
-            FunctionRef::ID => match self.data_funs.get(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val()),
-                None => None,
-            },
-            ConstantRef::ID => match self.data_consts.get(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val()),
-                None => None,
-            },
+        let raw = r.raw();
+        match raw.get_id() {
+            InstructionRef::ID => self.data_ins.get(raw).map(|obj| obj.val()),
+            BasicBlockRef::ID => self.data_bbs.get(raw).map(|obj| obj.val()),
+            ArgumentRef::ID => self.data_args.get(raw).map(|obj| obj.val()),
+            FunctionRef::ID => self.data_funs.get(raw).map(|obj| obj.val()),
+            ConstantRef::ID => self.data_consts.get(raw).map(|obj| obj.val()),
+            GlobalRef::ID => self.data_globals.get(raw).map(|obj| obj.val()),
             _ => unreachable!(),
         }
     }
 
     pub fn get_data_value_mut(&mut self, r: ValueRef) -> Option<&mut Value> {
-        let raw = RawValueRef::new(r.to_index());
-        let (id, pos) = (raw.get_id(), raw.get_pos());
-        match id {
-            InstructionRef::ID => match self.data_ins.get_mut(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val_mut()),
-                None => None,
-            },
-            BasicBlockRef::ID => match self.data_bbs.get_mut(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val_mut()),
-                None => None,
-            },
-            ArgumentRef::ID => match self.data_args.get_mut(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val_mut()),
-                None => None,
-            },
-            FunctionRef::ID => match self.data_funs.get_mut(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val_mut()),
-                None => None,
-            },
-            ConstantRef::ID => match self.data_consts.get_mut(pos).expect("invalid ref") {
-                Some(obj) => Some(obj.val_mut()),
-                None => None,
-            },
+        let raw = r.raw();
+        match raw.get_id() {
+            InstructionRef::ID => self.data_ins.get_mut(raw).map(|obj| obj.val_mut()),
+            BasicBlockRef::ID => self.data_bbs.get_mut(raw).map(|obj| obj.val_mut()),
+            ArgumentRef::ID => self.data_args.get_mut(raw).map(|obj| obj.val_mut()),
+            FunctionRef::ID => self.data_funs.get_mut(raw).map(|obj| obj.val_mut()),
+            ConstantRef::ID => self.data_consts.get_mut(raw).map(|obj| obj.val_mut()),
+            GlobalRef::ID => self.data_globals.get_mut(raw).map(|obj| obj.val_mut()),
             _ => unreachable!(),
         }
     }
 
     pub fn get_data_instruction(&self, r: InstructionRef) -> Option<&Instruction> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_ins.get(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_ins.get(r.get_raw())
     }
 
     pub fn get_data_instruction_mut(&mut self, r: InstructionRef) -> Option<&mut Instruction> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_ins.get_mut(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_ins.get_mut(r.get_raw())
     }
 
     pub fn get_data_basic_block(&self, r: BasicBlockRef) -> Option<&BasicBlock> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_bbs.get(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_bbs.get(r.get_raw())
     }
 
     pub fn get_data_basic_block_mut(&mut self, r: BasicBlockRef) -> Option<&mut BasicBlock> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_bbs.get_mut(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_bbs.get_mut(r.get_raw())
     }
 
     pub fn get_data_argument(&self, r: ArgumentRef) -> Option<&Argument> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_args.get(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_args.get(r.get_raw())
     }
 
     pub fn get_data_argument_mut(&mut self, r: ArgumentRef) -> Option<&mut Argument> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_args.get_mut(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_args.get_mut(r.get_raw())
     }
 
     pub fn get_data_function(&self, r: FunctionRef) -> Option<&Function> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_funs.get(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_funs.get(r.get_raw())
     }
 
     pub fn get_data_function_mut(&mut self, r: FunctionRef) -> Option<&mut Function> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_funs.get_mut(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_funs.get_mut(r.get_raw())
     }
 
     pub fn get_data_constant(&self, r: ConstantRef) -> Option<&Constant> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_consts.get(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_consts.get(r.get_raw())
     }
 
+    #[allow(dead_code)]
     pub fn get_data_constant_mut(&mut self, r: ConstantRef) -> Option<&mut Constant> {
-        let pos = RawValueRef::new(r.to_index()).get_pos();
-        match self.data_consts.get_mut(pos).expect("invalid ref") {
-            Some(obj) => Some(obj),
-            None => None,
-        }
+        self.data_consts.get_mut(r.get_raw())
+    }
+
+    pub fn get_data_global(&self, r: GlobalRef) -> Option<&Global> {
+        self.data_globals.get(r.get_raw())
+    }
+
+    pub fn get_data_global_mut(&mut self, r: GlobalRef) -> Option<&mut Global> {
+        self.data_globals.get_mut(r.get_raw())
     }
 
     fn make_value(&mut self, name: &str, id: ValueRef, is_def: bool, ops: &[ValueRef]) -> Value {
         let res = Value::new(name, id, is_def, ops);
 
-        for op in res.ops() {
+        for (idx, op) in res.ops().iter().enumerate() {
             if let Some(op) = op.own_mut(self) {
-                op.users_add(id);
+                op.uses_add(Use::new(id, idx));
             }
         }
 
@@ -180,21 +125,164 @@ impl Context {
     pub fn make_ins(
         &mut self,
         name: &str,
-        opname: &str,
+        opcode: Opcode,
         is_def: bool,
         ops: &[ValueRef],
     ) -> InstructionRef {
-        let vref: ValueRef = RawValueRef::make(InstructionRef::ID, self.data_ins.len()).into();
-        let iref: InstructionRef = vref.raw().into();
+        let vref: ValueRef = self.data_ins.next_ref().into();
+        let ty = self.infer_type(opcode, is_def, ops);
         let val = self.make_value(name, vref, is_def, ops);
-        let ins = Instruction::new(val, opname);
-        self.data_ins.push(Some(Box::new(ins)));
-        iref
+        self.data_ins
+            .insert(Instruction::new(val, opcode, ty))
+            .into()
+    }
+
+    // Type of a value, void for basic blocks and functions
+    // A global is the address of its data
+    pub fn val_type(&self, v: ValueRef) -> Type {
+        match v.to_enum() {
+            ValueRefEnum::Ins(i) => i.own(self).unwrap().ty(),
+            ValueRefEnum::Arg(a) => a.own(self).unwrap().ty(),
+            ValueRefEnum::Const(_) => Type::I64,
+            ValueRefEnum::Global(_) => Type::Ptr,
+            ValueRefEnum::BB(_) | ValueRefEnum::Fun(_) => Type::Void,
+        }
+    }
+
+    // Type of the result of `opcode ops`
+    pub fn infer_type(&self, opcode: Opcode, is_def: bool, ops: &[ValueRef]) -> Type {
+        if !is_def {
+            return Type::Void;
+        }
+
+        match opcode {
+            _ if opcode.infos().is_cmp() => Type::I1,
+            Opcode::And | Opcode::Or | Opcode::Xor => self.val_type(ops[0]),
+            Opcode::Select => self.val_type(ops[1]),
+            Opcode::Alloca | Opcode::Offset => Type::Ptr,
+            Opcode::Phi => ops
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .map(|v| self.val_type(*v))
+                .next()
+                .unwrap_or(Type::I64),
+            Opcode::Call => match ops[0].to_enum() {
+                ValueRefEnum::Fun(f) => f.own(self).unwrap().ret_ty(),
+                _ => Type::I64,
+            },
+            _ => Type::I64,
+        }
+    }
+
+    // Infer again the type of `ins` from its operands
+    // Returns true if it changed
+    pub fn ins_update_type(&mut self, ins: InstructionRef) -> bool {
+        let ins_obj = ins.own(self).unwrap();
+        let ty = self.infer_type(
+            ins_obj.opcode(),
+            ins_obj.val().is_def(),
+            ins_obj.val().ops(),
+        );
+        let ins_obj = ins.own_mut(self).unwrap();
+        let changed = ins_obj.ty() != ty;
+        ins_obj.set_ty(ty);
+        changed
+    }
+
+    // Attributes of the function called by `ins`, None if not a call
+    pub fn ins_callee_attrs(&self, ins: InstructionRef) -> Option<Attrs> {
+        let ins = ins.own(self).unwrap();
+        if ins.opcode() != Opcode::Call {
+            return None;
+        }
+        match ins.val().ops()[0].to_enum() {
+            ValueRefEnum::Fun(f) => Some(f.own(self).unwrap().attrs()),
+            _ => None,
+        }
+    }
+
+    // `ins` can't be removed even if its value is unused
+    // Calls have side effects unless the callee is pure
+    pub fn ins_has_side_effects(&self, ins: InstructionRef) -> bool {
+        match self.ins_callee_attrs(ins) {
+            Some(attrs) => attrs.has_side_effects(),
+            None => ins.own(self).unwrap().infos().has_side_effects(),
+        }
     }
 
+    #[allow(dead_code)]
+    pub fn ins_reads_memory(&self, ins: InstructionRef) -> bool {
+        match self.ins_callee_attrs(ins) {
+            Some(attrs) => attrs.reads_memory(),
+            None => ins.own(self).unwrap().infos().reads_memory(),
+        }
+    }
+
+    // Control never goes past `ins`
+    pub fn ins_is_noreturn(&self, ins: InstructionRef) -> bool {
+        self.ins_callee_attrs(ins)
+            .is_some_and(|attrs| attrs.is_noreturn())
+    }
+
+    // Detach `ins` from its basic block and remove it from the users of its
+    // operands before erasing it
+    // Panics if `ins` still has users other than itself, leaving it untouched
     pub fn erase_ins(&mut self, ins: InstructionRef) {
-        let pos = RawValueRef::new(ins.to_index()).get_pos();
-        self.data_ins[pos] = None;
+        let ins_val = ins.own(self).unwrap().val();
+        if let Some(user) = ins_val.users().find(|user| *user != ins.into()) {
+            panic!(
+                "Erase instruction {} still used by {}",
+                ins_val.name(),
+                user.own(self).map(|u| u.name()).unwrap_or("<erased>")
+            );
+        }
+
+        self.ins_detach(ins);
+        self.ins_set_ops(ins, &[]);
+        self.data_ins.erase(ins.get_raw());
+    }
+
+    // Erase instructions only used by each other, e.g. a dead cycle of phis
+    // Panics if one of them is used outside the group, leaving them untouched
+    pub fn erase_ins_group(&mut self, group: &[InstructionRef]) {
+        for ins in group {
+            let ins_val = ins.own(self).unwrap().val();
+            let outside = ins_val
+                .users()
+                .find(|user| !group.iter().any(|x| ValueRef::from(*x) == *user));
+            if let Some(user) = outside {
+                panic!(
+                    "Erase instruction {} still used by {}",
+                    ins_val.name(),
+                    user.own(self).map(|u| u.name()).unwrap_or("<erased>")
+                );
+            }
+        }
+
+        for ins in group {
+            self.ins_set_ops(*ins, &[]);
+        }
+        for ins in group {
+            self.erase_ins(*ins);
+        }
+    }
+
+    // Rewrite every use of `old` into a use of `new`
+    pub fn replace_all_uses_with(&mut self, old: ValueRef, new: ValueRef) {
+        if old == new {
+            return;
+        }
+
+        // The use list moves at once, in O(uses)
+        let uses = old.own_mut(self).unwrap().uses_take();
+        for u in &uses {
+            let user: InstructionRef = u.user().raw().into();
+            user.own_mut(self).unwrap().val_mut().ops_mut()[u.idx()] = new;
+        }
+        if let Some(new_val) = new.own_mut(self) {
+            new_val.uses_extend(&uses);
+        }
     }
 
     pub fn ins_set_op(&mut self, ins: InstructionRef, idx: usize, new_val: ValueRef) {
@@ -206,18 +294,95 @@ impl Context {
 
         ins_val.ops_mut()[idx] = new_val;
 
-        // Instruction may use same val for multiple operands
-        if !ins_val.has_op(old_val) {
-            if let Some(old_val) = old_val.own_mut(self) {
-                old_val.users_del(ins.into())
-            }
+        if let Some(old_val) = old_val.own_mut(self) {
+            old_val.uses_del(Use::new(ins.into(), idx))
         }
 
         if let Some(new_val) = new_val.own_mut(self) {
-            new_val.users_add(ins.into())
+            new_val.uses_add(Use::new(ins.into(), idx))
         }
     }
 
+    // Replace the whole operand list, the number of operands may change
+    pub fn ins_set_ops(&mut self, ins: InstructionRef, new_ops: &[ValueRef]) {
+        let old_ops = ins.own(self).unwrap().val().ops().to_vec();
+        ins.own_mut(self).unwrap().val_mut().set_ops(new_ops);
+
+        for (idx, op) in old_ops.iter().enumerate() {
+            if let Some(op) = op.own_mut(self) {
+                op.uses_del(Use::new(ins.into(), idx));
+            }
+        }
+
+        for (idx, op) in new_ops.iter().enumerate() {
+            if let Some(op) = op.own_mut(self) {
+                op.uses_add(Use::new(ins.into(), idx));
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn phi_add_incoming(&mut self, phi: InstructionRef, bb: BasicBlockRef, val: ValueRef) {
+        let phi_obj = phi.own(self).unwrap();
+        let view = phi_obj.as_phi().expect("not a phi");
+        if view.value_idx(bb).is_some() {
+            panic!(
+                "Phi {} already has an incoming value for {}",
+                phi_obj.val().name(),
+                bb.own(self).unwrap().val().name()
+            );
+        }
+
+        let mut ops = phi_obj.val().ops().to_vec();
+        ops.push(bb.into());
+        ops.push(val);
+        self.ins_set_ops(phi, &ops);
+    }
+
+    pub fn phi_remove_incoming(&mut self, phi: InstructionRef, bb: BasicBlockRef) {
+        let idx = self.phi_value_idx(phi, bb);
+        let mut ops = phi.own(self).unwrap().val().ops().to_vec();
+        ops.drain(idx - 1..=idx);
+        self.ins_set_ops(phi, &ops);
+    }
+
+    // The value incoming from `old` now comes from `new`
+    pub fn phi_retarget_incoming(
+        &mut self,
+        phi: InstructionRef,
+        old: BasicBlockRef,
+        new: BasicBlockRef,
+    ) {
+        let idx = self.phi_value_idx(phi, old);
+        self.ins_set_op(phi, idx - 1, new.into());
+    }
+
+    // The value incoming from `bb` is now `val`
+    pub fn phi_set_incoming_value(
+        &mut self,
+        phi: InstructionRef,
+        bb: BasicBlockRef,
+        val: ValueRef,
+    ) {
+        let idx = self.phi_value_idx(phi, bb);
+        self.ins_set_op(phi, idx, val);
+    }
+
+    fn phi_value_idx(&self, phi: InstructionRef, bb: BasicBlockRef) -> usize {
+        let phi_obj = phi.own(self).unwrap();
+        phi_obj
+            .as_phi()
+            .expect("not a phi")
+            .value_idx(bb)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Phi {} has no incoming value for {}",
+                    phi_obj.val().name(),
+                    bb.own(self).unwrap().val().name()
+                )
+            })
+    }
+
     pub fn ins_detach(&mut self, ins: InstructionRef) {
         let ins_obj = ins.own_mut(self).unwrap();
         if ins_obj.parent().is_none() {
@@ -263,17 +428,28 @@ impl Context {
     }
 
     pub fn make_bb(&mut self, name: &str) -> BasicBlockRef {
-        let vref: ValueRef = RawValueRef::make(BasicBlockRef::ID, self.data_bbs.len()).into();
-        let bref: BasicBlockRef = vref.raw().into();
+        let vref: ValueRef = self.data_bbs.next_ref().into();
         let val = self.make_value(name, vref, true, &[]);
-        let bb = BasicBlock::new(val);
-        self.data_bbs.push(Some(Box::new(bb)));
-        bref
+        self.data_bbs.insert(BasicBlock::new(val)).into()
     }
 
+    // Panics if `bb` is still in a function, has instructions or users,
+    // leaving it untouched
     pub fn erase_bb(&mut self, bb: BasicBlockRef) {
-        let pos = RawValueRef::new(bb.to_index()).get_pos();
-        self.data_bbs[pos] = None;
+        let bb_obj = bb.own(self).unwrap();
+        let name = bb_obj.val().name();
+        if let Some(fun) = bb_obj.parent() {
+            panic!(
+                "Erase block {} still in function {}",
+                name,
+                fun.own(self).unwrap().val().name()
+            );
+        }
+        if !bb_obj.ins().is_empty() {
+            panic!("Erase block {} still has instructions", name);
+        }
+        self.check_unused(bb.into(), "block", name);
+        self.data_bbs.erase(bb.get_raw());
     }
 
     pub fn bb_detach(&mut self, bb: BasicBlockRef) {
@@ -309,6 +485,7 @@ impl Context {
         fun.own_mut(self).unwrap().insert_before(bb, pos);
     }
 
+    #[allow(dead_code)]
     pub fn bb_insert_after(&mut self, bb: BasicBlockRef, pos: BasicBlockRef) {
         let fun = pos.own(self).unwrap().parent().unwrap();
         let bb_obj = bb.own_mut(self).unwrap();
@@ -321,21 +498,20 @@ impl Context {
     }
 
     fn make_arg(&mut self, name: &str, arg_pos: usize, fun: FunctionRef) -> ArgumentRef {
-        let vref: ValueRef = RawValueRef::make(ArgumentRef::ID, self.data_args.len()).into();
-        let aref: ArgumentRef = vref.raw().into();
+        let vref: ValueRef = self.data_args.next_ref().into();
         let val = self.make_value(name, vref, true, &[]);
-        let arg = Argument::new(val, arg_pos, fun);
-        self.data_args.push(Some(Box::new(arg)));
-        aref
+        self.data_args
+            .insert(Argument::new(val, arg_pos, fun))
+            .into()
     }
 
     fn erase_arg(&mut self, arg: ArgumentRef) {
-        let pos = RawValueRef::new(arg.to_index()).get_pos();
-        self.data_args[pos] = None;
+        self.check_unused(arg.into(), "argument", arg.own(self).unwrap().val().name());
+        self.data_args.erase(arg.get_raw());
     }
 
     pub fn make_fun(&mut self, name: &str, args_count: usize, is_decl: bool) -> FunctionRef {
-        let vref: ValueRef = RawValueRef::make(FunctionRef::ID, self.data_funs.len()).into();
+        let vref: ValueRef = self.data_funs.next_ref().into();
         let fref: FunctionRef = vref.raw().into();
         let val = self.make_value(name, vref, true, &[]);
 
@@ -343,14 +519,26 @@ impl Context {
             .map(|pos| self.make_arg("", pos, fref))
             .collect();
 
-        let fun = Function::new(val, &args[..], is_decl);
-        self.data_funs.push(Some(Box::new(fun)));
-        fref
+        self.data_funs
+            .insert(Function::new(val, &args[..], is_decl))
+            .into()
     }
 
+    // Erase `fun` and its arguments
+    // Panics if `fun` still has blocks or users, leaving it untouched
+    #[allow(dead_code)]
     pub fn erase_fun(&mut self, fun: FunctionRef) {
-        let pos = RawValueRef::new(fun.to_index()).get_pos();
-        self.data_funs[pos] = None;
+        let fun_obj = fun.own(self).unwrap();
+        let name = fun_obj.val().name();
+        if !fun_obj.is_decl() && !fun_obj.bbs().is_empty() {
+            panic!("Erase function {} still has blocks", name);
+        }
+        self.check_unused(fun.into(), "function", name);
+
+        for arg in fun.own(self).unwrap().args().to_vec() {
+            self.erase_arg(arg);
+        }
+        self.data_funs.erase(fun.get_raw());
     }
 
     pub fn fun_get_arg_mut(&mut self, fun: FunctionRef, idx: usize) -> &mut Argument {
@@ -359,24 +547,62 @@ impl Context {
     }
 
     pub fn funs<'a>(&'a self) -> impl Iterator<Item = FunctionRef> + 'a {
-        self.data_funs
-            .iter()
-            .filter(|f| f.is_some())
-            .map(|f| f.as_ref().unwrap().id())
+        self.data_funs.iter().map(|f| f.id())
     }
 
     pub fn make_const(&mut self, name: &str, const_int: i64) -> ConstantRef {
-        let vref: ValueRef = RawValueRef::make(ConstantRef::ID, self.data_consts.len()).into();
-        let cref: ConstantRef = vref.raw().into();
+        let vref: ValueRef = self.data_consts.next_ref().into();
         let val = self.make_value(name, vref, true, &[]);
-        let c = Constant::new(val, const_int);
-        self.data_consts.push(Some(Box::new(c)));
-        cref
+        self.data_consts
+            .insert(Constant::new(val, const_int))
+            .into()
     }
 
+    // Panics if `c` still has users, leaving it untouched
     pub fn erase_const(&mut self, c: ConstantRef) {
-        let pos = RawValueRef::new(c.to_index()).get_pos();
-        self.data_consts[pos] = None;
+        let value = c.own(self).unwrap().const_int().to_string();
+        self.check_unused(c.into(), "constant", &value);
+        self.data_consts.erase(c.get_raw());
+    }
+
+    pub fn consts<'a>(&'a self) -> impl Iterator<Item = ConstantRef> + 'a {
+        self.data_consts.iter().map(|c| c.id())
+    }
+
+    pub fn make_global(
+        &mut self,
+        name: &str,
+        is_const: bool,
+        len: usize,
+        init: &[i64],
+    ) -> GlobalRef {
+        let vref: ValueRef = self.data_globals.next_ref().into();
+        let val = self.make_value(name, vref, true, &[]);
+        self.data_globals
+            .insert(Global::new(val, is_const, len, init))
+            .into()
+    }
+
+    // Panics if `g` still has users, leaving it untouched
+    #[allow(dead_code)]
+    pub fn erase_global(&mut self, g: GlobalRef) {
+        self.check_unused(g.into(), "global", g.own(self).unwrap().val().name());
+        self.data_globals.erase(g.get_raw());
+    }
+
+    fn check_unused(&self, v: ValueRef, kind: &str, name: &str) {
+        if let Some(user) = v.own(self).unwrap().users().next() {
+            panic!(
+                "Erase {} {} still used by {}",
+                kind,
+                name,
+                user.own(self).map(|u| u.name()).unwrap_or("<erased>")
+            );
+        }
+    }
+
+    pub fn globals<'a>(&'a self) -> impl Iterator<Item = GlobalRef> + 'a {
+        self.data_globals.iter().map(|g| g.id())
     }
 }
 
@@ -384,7 +610,7 @@ impl Context {
 fn simple_function() {
     let mut ctx = Context::new();
     let my_num = ctx.make_const("", 671);
-    let my_ret = ctx.make_ins("ret1", "ret", false, &[my_num.into()]);
+    let my_ret = ctx.make_ins("ret1", Opcode::Ret, false, &[my_num.into()]);
     let my_bb = ctx.make_bb("my_bb");
     ctx.ins_insert_in(my_ret, my_bb);
 
@@ -415,3 +641,252 @@ fn simple_function() {
         assert_eq!(f.own(&ctx).unwrap().val().name(), "foo");
     }
 }
+
+#[test]
+fn erase_recycle() {
+    let mut ctx = Context::new();
+    let c1 = ctx.make_const("", 1);
+    let c2 = ctx.make_const("", 2);
+    let ins = ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c2.into()]);
+
+    ctx.erase_ins(ins);
+    assert!(ins.own(&ctx).is_none());
+
+    // The slot is reused, but the stale ref doesn't see the new instruction
+    let new_ins = ctx.make_ins("y", Opcode::Sub, true, &[c1.into(), c2.into()]);
+    assert_eq!(new_ins.get_raw().get_pos(), ins.get_raw().get_pos());
+    assert_ne!(new_ins, ins);
+    assert!(ins.own(&ctx).is_none());
+    let ins_val: ValueRef = ins.into();
+    assert!(ins_val.own(&ctx).is_none());
+    assert_eq!(new_ins.own(&ctx).unwrap().val().name(), "y");
+}
+
+#[test]
+fn erase_def_use() {
+    let mut ctx = Context::new();
+    let bb = ctx.make_bb("bb");
+    let c1 = ctx.make_const("", 1);
+    let c2 = ctx.make_const("", 2);
+    let x = ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c2.into()]);
+    let y = ctx.make_ins("y", Opcode::Mul, true, &[x.into(), x.into()]);
+    let ret = ctx.make_ins("", Opcode::Ret, false, &[y.into()]);
+    ctx.ins_insert_in(x, bb);
+    ctx.ins_insert_in(y, bb);
+    ctx.ins_insert_in(ret, bb);
+
+    ctx.replace_all_uses_with(x.into(), c2.into());
+    assert_eq!(y.own(&ctx).unwrap().val().ops(), &[c2.into(), c2.into()]);
+    assert!(!x.own(&ctx).unwrap().val().has_uses());
+    let c2_uses: Vec<Use> = c2.own(&ctx).unwrap().val().uses().collect();
+    assert!(c2_uses.contains(&Use::new(y.into(), 0)));
+    assert!(c2_uses.contains(&Use::new(y.into(), 1)));
+
+    ctx.erase_ins(x);
+    assert!(x.own(&ctx).is_none());
+    assert_eq!(bb.own(&ctx).unwrap().ins(), &[y, ret]);
+    assert!(!c1.own(&ctx).unwrap().val().has_uses());
+    assert!(!c2.own(&ctx).unwrap().val().users().any(|u| u == x.into()));
+}
+
+#[test]
+#[should_panic(expected = "Erase instruction x still used by y")]
+fn erase_used() {
+    let mut ctx = Context::new();
+    let c1 = ctx.make_const("", 1);
+    let x = ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c1.into()]);
+    ctx.make_ins("y", Opcode::Add, true, &[x.into(), c1.into()]);
+    ctx.erase_ins(x);
+}
+
+#[test]
+fn erase_used_untouched() {
+    // A refused erase keeps the instruction and its def-use lists
+    let mut ctx = Context::new();
+    let bb = ctx.make_bb("bb");
+    let c1 = ctx.make_const("", 1);
+    let x = ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c1.into()]);
+    let y = ctx.make_ins("y", Opcode::Add, true, &[x.into(), c1.into()]);
+    ctx.ins_insert_in(x, bb);
+    ctx.ins_insert_in(y, bb);
+
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| ctx.erase_ins(x)));
+    assert!(res.is_err());
+    assert_eq!(bb.own(&ctx).unwrap().ins(), &[x, y]);
+    assert_eq!(x.own(&ctx).unwrap().val().ops(), &[c1.into(), c1.into()]);
+    assert_eq!(c1.own(&ctx).unwrap().val().uses().count(), 3);
+}
+
+#[test]
+fn erase_group_used_untouched() {
+    // %x and %y form a cycle, %z uses %y from outside the group
+    let mut ctx = Context::new();
+    let bb = ctx.make_bb("bb");
+    let c1 = ctx.make_const("", 1);
+    let x = ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c1.into()]);
+    let y = ctx.make_ins("y", Opcode::Add, true, &[x.into(), c1.into()]);
+    let z = ctx.make_ins("z", Opcode::Add, true, &[y.into(), c1.into()]);
+    ctx.ins_set_op(x, 0, y.into());
+    for ins in [x, y, z] {
+        ctx.ins_insert_in(ins, bb);
+    }
+
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        ctx.erase_ins_group(&[x, y])
+    }));
+    let msg = res.unwrap_err().downcast::<String>().unwrap();
+    assert_eq!(*msg, "Erase instruction y still used by z");
+    assert_eq!(bb.own(&ctx).unwrap().ins(), &[x, y, z]);
+    assert_eq!(x.own(&ctx).unwrap().val().ops(), &[y.into(), c1.into()]);
+    assert_eq!(y.own(&ctx).unwrap().val().ops(), &[x.into(), c1.into()]);
+    assert_eq!(y.own(&ctx).unwrap().val().uses().count(), 2);
+}
+
+#[test]
+fn erase_const_recycle() {
+    let mut ctx = Context::new();
+    let c1 = ctx.make_const("", 1);
+    let x = ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c1.into()]);
+    ctx.erase_ins(x);
+    ctx.erase_const(c1);
+    assert!(c1.own(&ctx).is_none());
+    assert_eq!(ctx.consts().count(), 0);
+
+    let c2 = ctx.make_const("", 2);
+    assert_eq!(c2.get_raw().get_pos(), c1.get_raw().get_pos());
+    assert_ne!(c2, c1);
+}
+
+#[test]
+#[should_panic(expected = "Erase constant 1 still used by x")]
+fn erase_const_used() {
+    let mut ctx = Context::new();
+    let c1 = ctx.make_const("", 1);
+    ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c1.into()]);
+    ctx.erase_const(c1);
+}
+
+#[test]
+#[should_panic(expected = "Erase block bb still in function f")]
+fn erase_bb_in_fun() {
+    let mut ctx = Context::new();
+    let fun = ctx.make_fun("f", 0, false);
+    let bb = ctx.make_bb("bb");
+    ctx.bb_insert_in(bb, fun);
+    ctx.erase_bb(bb);
+}
+
+#[test]
+#[should_panic(expected = "Erase block bb still used by")]
+fn erase_bb_used() {
+    let mut ctx = Context::new();
+    let bb = ctx.make_bb("bb");
+    ctx.make_ins("", Opcode::B, false, &[bb.into()]);
+    ctx.erase_bb(bb);
+}
+
+#[test]
+#[should_panic(expected = "Erase block bb still has instructions")]
+fn erase_bb_not_empty() {
+    let mut ctx = Context::new();
+    let bb = ctx.make_bb("bb");
+    let c1 = ctx.make_const("", 1);
+    let ret = ctx.make_ins("", Opcode::Ret, false, &[c1.into()]);
+    ctx.ins_insert_in(ret, bb);
+    ctx.erase_bb(bb);
+}
+
+#[test]
+#[should_panic(expected = "Erase function g still used by x")]
+fn erase_fun_used() {
+    let mut ctx = Context::new();
+    let g = ctx.make_fun("g", 0, true);
+    ctx.make_ins("x", Opcode::Call, true, &[g.into()]);
+    ctx.erase_fun(g);
+}
+
+#[test]
+fn replace_many_uses() {
+    // Linear in the number of uses: quadratic would not finish in time
+    const COUNT: usize = 100_000;
+    let mut ctx = Context::new();
+    let c1 = ctx.make_const("", 1);
+    let c2 = ctx.make_const("", 2);
+    let x = ctx.make_ins("x", Opcode::Add, true, &[c1.into(), c1.into()]);
+    let users: Vec<InstructionRef> = (0..COUNT)
+        .map(|_| ctx.make_ins("", Opcode::Add, true, &[x.into(), x.into()]))
+        .collect();
+
+    ctx.replace_all_uses_with(x.into(), c2.into());
+    assert!(!x.own(&ctx).unwrap().val().has_uses());
+    assert_eq!(c2.own(&ctx).unwrap().val().uses().count(), 2 * COUNT);
+    assert_eq!(
+        users[7].own(&ctx).unwrap().val().ops(),
+        &[c2.into(), c2.into()]
+    );
+}
+
+#[test]
+fn use_lists() {
+    let mut ctx = Context::new();
+    let c1 = ctx.make_const("", 1);
+    let i = ctx.make_ins("i", Opcode::Add, true, &[c1.into(), c1.into()]);
+    let x = ctx.make_ins("x", Opcode::Mul, true, &[i.into(), i.into()]);
+
+    let uses: Vec<Use> = i.own(&ctx).unwrap().val().uses().collect();
+    assert_eq!(uses, vec![Use::new(x.into(), 0), Use::new(x.into(), 1)]);
+
+    // Rewriting one operand keeps the other use
+    ctx.ins_set_op(x, 1, c1.into());
+    let uses: Vec<Use> = i.own(&ctx).unwrap().val().uses().collect();
+    assert_eq!(uses, vec![Use::new(x.into(), 0)]);
+    assert_eq!(c1.own(&ctx).unwrap().val().uses().count(), 3);
+
+    ctx.ins_set_ops(x, &[c1.into(), i.into()]);
+    let uses: Vec<Use> = i.own(&ctx).unwrap().val().uses().collect();
+    assert_eq!(uses, vec![Use::new(x.into(), 1)]);
+    assert_eq!(c1.own(&ctx).unwrap().val().uses().count(), 3);
+}
+
+#[test]
+fn phi_incoming() {
+    let mut ctx = Context::new();
+    let b0 = ctx.make_bb("b0");
+    let b1 = ctx.make_bb("b1");
+    let b2 = ctx.make_bb("b2");
+    let c1 = ctx.make_const("", 1);
+    let c2 = ctx.make_const("", 2);
+    let phi = ctx.make_ins("x", Opcode::Phi, true, &[b0.into(), c1.into()]);
+
+    ctx.phi_add_incoming(phi, b1, c2.into());
+    ctx.phi_add_incoming(phi, b2, c1.into());
+    let incoming: Vec<_> = phi
+        .own(&ctx)
+        .unwrap()
+        .as_phi()
+        .unwrap()
+        .incoming()
+        .collect();
+    assert_eq!(
+        incoming,
+        vec![(b0, c1.into()), (b1, c2.into()), (b2, c1.into())]
+    );
+
+    // Operand indices of the uses follow the removed pair
+    ctx.phi_remove_incoming(phi, b0);
+    let mut uses: Vec<Use> = c1.own(&ctx).unwrap().val().uses().collect();
+    uses.sort_by_key(|u| u.idx());
+    assert_eq!(uses, vec![Use::new(phi.into(), 3)]);
+    assert!(!b0.own(&ctx).unwrap().val().has_uses());
+
+    ctx.phi_retarget_incoming(phi, b1, b0);
+    ctx.phi_set_incoming_value(phi, b2, c2.into());
+    let view_ins = phi.own(&ctx).unwrap();
+    let view = view_ins.as_phi().unwrap();
+    assert_eq!(view.incoming_count(), 2);
+    assert_eq!(view.incoming_value(b0), Some(c2.into()));
+    assert_eq!(view.incoming_value(b1), None);
+    assert_eq!(view.incoming_value(b2), Some(c2.into()));
+    assert!(!c1.own(&ctx).unwrap().val().has_uses());
+    assert_eq!(c2.own(&ctx).unwrap().val().uses().count(), 2);
+}
@@ -0,0 +1,166 @@
+// Dead Code Elimination
+// Marks live the terminators, the instructions with side effects (calls are
+// only free of them when the callee is `pure` or `readnone`) and the
+// instructions without a value, then the operands of live instructions, and
+// removes everything else. Values only used by each other, like a dead cycle
+// of phis, are removed too.
+
+use crate::context::Context;
+use crate::instruction::Instruction;
+use crate::remarks::Remarks;
+use crate::valueref::{ConstantRef, FunctionRef, InstructionRef, ValueRefEnum};
+use crate::visitor::{self, Visitor};
+
+use std::collections::HashSet;
+
+const PASS_NAME: &str = "dce";
+
+// Instructions kept whether their value is used or not
+fn is_root(ctx: &Context, ins: &Instruction) -> bool {
+    !ins.val().is_def() || ins.infos().is_term() || ctx.ins_has_side_effects(ins.id())
+}
+
+// Live instructions, and all the instructions in order
+#[derive(Default)]
+struct Mark {
+    live: HashSet<InstructionRef>,
+    worklist: Vec<InstructionRef>,
+    all: Vec<InstructionRef>,
+}
+
+impl Mark {
+    fn mark(&mut self, ins: InstructionRef) {
+        if self.live.insert(ins) {
+            self.worklist.push(ins);
+        }
+    }
+}
+
+impl Visitor for Mark {
+    fn visit_ins(&mut self, ctx: &Context, ins: &Instruction) {
+        self.all.push(ins.id());
+        if is_root(ctx, ins) {
+            self.mark(ins.id());
+        }
+    }
+}
+
+pub fn run_fun(ctx: &mut Context, fun: FunctionRef, remarks: &mut Remarks) {
+    let mut mark = Mark::default();
+    visitor::walk_fun(ctx, fun, &mut mark);
+    while let Some(ins) = mark.worklist.pop() {
+        for op in ins.own(ctx).unwrap().val().ops() {
+            if let ValueRefEnum::Ins(op) = op.to_enum() {
+                mark.mark(op);
+            }
+        }
+    }
+
+    let fun_name = fun.own(ctx).unwrap().val().name().to_string();
+    let live = mark.live;
+    let dead: Vec<InstructionRef> = mark
+        .all
+        .into_iter()
+        .filter(|ins| !live.contains(ins))
+        .collect();
+    for ins in &dead {
+        let ins_obj = ins.own(ctx).unwrap();
+        remarks.applied(
+            PASS_NAME,
+            &fun_name,
+            ins_obj.val().name(),
+            ins_obj.loc(),
+            "removed unused value".to_string(),
+        );
+    }
+    ctx.erase_ins_group(&dead);
+}
+
+pub fn run(ctx: &mut Context, remarks: &mut Remarks) {
+    let funs: Vec<FunctionRef> = ctx.funs().collect();
+    for fun in funs {
+        if !fun.own(ctx).unwrap().is_decl() {
+            run_fun(ctx, fun, remarks);
+        }
+    }
+
+    // Constants of the removed and rewritten instructions, their slots are
+    // recycled
+    let unused: Vec<ConstantRef> = ctx
+        .consts()
+        .filter(|c| !c.own(ctx).unwrap().val().has_uses())
+        .collect();
+    for c in unused {
+        ctx.erase_const(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker;
+    use crate::gop;
+    use crate::loader;
+    use crate::test_utils::find_path;
+
+    fn run_file(path: &str) -> (String, String) {
+        let path = find_path(path);
+
+        let mut ctx = Context::new();
+        let mut remarks = Remarks::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        run(&mut ctx, &mut remarks);
+        checker::check_code(&ctx);
+
+        let mut os = vec![];
+        remarks.dump_text(&mut os).unwrap();
+        (
+            format!("{}", loader::build_gop(&ctx)),
+            String::from_utf8(os).unwrap(),
+        )
+    }
+
+    #[test]
+    fn dce_attrs() {
+        let (out, remarks) = run_file("examples/attrs.ir");
+        assert!(!out.contains("call %h0, @hash, %x"));
+        assert!(out.contains("call %h, @hash, %x"));
+        assert!(!out.contains("call %t, @trace, %x"));
+        assert!(out.contains("call %r, @read, %x"));
+        assert!(!out.contains("%d"));
+        assert!(!out.contains("load"));
+        assert!(out.contains("call @_std_exit, 1"));
+        assert!(remarks.contains("dce: check: %h0: removed unused value"));
+        assert!(remarks.contains("dce: check: %t: removed unused value"));
+        assert!(remarks.contains("dce: check: %d: removed unused value"));
+        assert!(remarks.contains("dce: check: %d2: removed unused value"));
+    }
+
+    #[test]
+    fn dce_dead_cycle() {
+        // %j and %j1 are only used by each other
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "cycle.ir",
+            "f:\n.fun i64, %n\n\nB0:\n\tb @loop\n\nloop:\n\tphi %i, @B0, 0, @loop, %i1\n\tphi %j, @B0, 0, @loop, %j1\n\tadd %i1, %i, 1\n\tadd %j1, %j, 2\n\tcmplt %c, %i1, %n\n\tbc %c, @loop, @end\n\nend:\n\tret %i1\n",
+        );
+        loader::load_gop(&mut ctx, &gmod);
+        let mut remarks = Remarks::new();
+        run(&mut ctx, &mut remarks);
+        checker::check_code(&ctx);
+
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(!out.contains("%j"));
+        assert!(out.contains("phi %i, @B0, 0, @loop, %i1"));
+        assert!(out.contains("add %i1, %i, 1"));
+        // The 2 of %j1 is erased with it
+        assert!(ctx.consts().all(|c| c.own(&ctx).unwrap().const_int() != 2));
+    }
+
+    #[test]
+    fn dce_keeps_examples() {
+        // Every value of the examples is used
+        let (_, remarks) = run_file("examples/mul_loop.ir");
+        assert!(remarks.is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+use std::rc::Rc;
+
+// Position in a source file, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugLoc {
+    file: Rc<str>,
+    line: usize,
+    col: usize,
+}
+
+impl DebugLoc {
+    pub fn new(file: Rc<str>, line: usize, col: usize) -> DebugLoc {
+        DebugLoc { file, line, col }
+    }
+
+    #[allow(dead_code)]
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    #[allow(dead_code)]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    #[allow(dead_code)]
+    pub fn col(&self) -> usize {
+        self.col
+    }
+}
+
+// `file:line:col: ` to prefix a diagnostic, empty without location
+pub fn loc_prefix(loc: Option<&DebugLoc>) -> String {
+    match loc {
+        Some(loc) => format!("{}: ", loc),
+        None => String::new(),
+    }
+}
+
+impl fmt::Display for DebugLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
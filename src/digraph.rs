@@ -21,6 +21,7 @@ impl Digraph {
         self.v
     }
 
+    #[allow(dead_code)]
     pub fn e(&self) -> usize {
         self.e
     }
@@ -40,6 +41,7 @@ impl Digraph {
         res
     }
 
+    #[allow(dead_code)]
     pub fn del_edge(&mut self, u: usize, v: usize) -> bool {
         let idx = self.adj_index(u, v);
         let v = self.adj.get_mut(idx).unwrap();
@@ -52,7 +54,7 @@ impl Digraph {
     }
 
     pub fn vertices(&self) -> impl Iterator<Item = usize> {
-        (0..self.v)
+        0..self.v
     }
 
     pub fn edges<'a>(&'a self) -> impl Iterator<Item = (usize, usize)> + 'a {
@@ -75,21 +77,17 @@ impl Digraph {
     }
 
     pub fn dump_tree<T: std::io::Write>(&self, os: &mut T) -> std::io::Result<()> {
-        write!(os, "digraph G {{\n")?;
+        writeln!(os, "digraph G {{")?;
 
         for u in self.vertices() {
-            write!(
-                os,
-                "  {} [ label=\"{}\" ];\n",
-                u, self.labels_vertex_names[u]
-            )?;
+            writeln!(os, "  {} [ label=\"{}\" ];", u, self.labels_vertex_names[u])?;
         }
 
         for (u, v) in self.edges() {
-            write!(os, "  {} -> {}\n", u, v)?;
+            writeln!(os, "  {} -> {}", u, v)?;
         }
 
-        write!(os, "}}\n")
+        writeln!(os, "}}")
     }
 
     pub fn save_tree(&self, path: &str) -> std::io::Result<()> {
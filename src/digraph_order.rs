@@ -29,7 +29,7 @@ impl DFS {
     fn run(&mut self, g: &Digraph) {
         self.dfs(g, self.start);
 
-        if (self.visit_unreachable) {
+        if self.visit_unreachable {
             for u in g.vertices() {
                 if !self.marked[u] {
                     self.dfs(g, u);
@@ -5,6 +5,7 @@ use crate::valueref::{BasicBlockRef, FunctionRef};
 use crate::vertex_adapter::VertexAdapter;
 
 pub struct DomTree {
+    #[allow(dead_code)]
     fun: FunctionRef,
     va: VertexAdapter<BasicBlockRef>,
     root: BasicBlockRef,
@@ -39,6 +40,7 @@ impl DomTree {
         self.va.v2o(self.idom[self.va.o2v(bb)])
     }
 
+    #[allow(dead_code)]
     pub fn dom(&self, bb: BasicBlockRef) -> Vec<BasicBlockRef> {
         let mut res = vec![];
         let mut node = bb;
@@ -50,6 +52,24 @@ impl DomTree {
         res
     }
 
+    // Returns true if every path from the root to `b` goes through `a`
+    pub fn dominates(&self, a: BasicBlockRef, b: BasicBlockRef) -> bool {
+        let mut node = b;
+        loop {
+            if node == a {
+                return true;
+            }
+            if node == self.root {
+                return false;
+            }
+            node = self.idom(node);
+        }
+    }
+
+    pub fn strictly_dominates(&self, a: BasicBlockRef, b: BasicBlockRef) -> bool {
+        a != b && self.dominates(a, b)
+    }
+
     pub fn succs<'a>(&'a self, bb: BasicBlockRef) -> impl Iterator<Item = BasicBlockRef> + 'a {
         self.tree
             .succs(self.va.o2v(bb))
@@ -64,7 +84,7 @@ impl DomTree {
 
     fn build(&mut self, ctx: &Context, cfg: &CFG) {
         self.init(cfg);
-        while !self.iterate(cfg) {}
+        while self.iterate(cfg) {}
         self.build_dom_tree(ctx);
     }
 
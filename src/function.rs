@@ -1,11 +1,19 @@
+use crate::attrs::{Attr, Attrs};
+use crate::comments::Comments;
+use crate::debug_loc::DebugLoc;
+use crate::types::Type;
 use crate::value::Value;
-use crate::valueref::{ArgumentRef, BasicBlockRef, FunctionRef, InstructionRef, ValueRef};
+use crate::valueref::{ArgumentRef, BasicBlockRef, FunctionRef};
 
 pub struct Function {
     val: Value,
     args: Vec<ArgumentRef>,
     is_decl: bool,
+    ret_ty: Type,
     bbs_list: Vec<BasicBlockRef>,
+    loc: Option<DebugLoc>,
+    comments: Comments,
+    attrs: Attrs,
 }
 
 impl Function {
@@ -14,7 +22,11 @@ impl Function {
             val,
             args: args.to_vec(),
             is_decl,
+            ret_ty: Type::I64,
             bbs_list: vec![],
+            loc: None,
+            comments: Comments::default(),
+            attrs: Attrs::default(),
         }
     }
 
@@ -38,16 +50,54 @@ impl Function {
         self.is_decl
     }
 
+    pub fn set_decl(&mut self, is_decl: bool) {
+        self.is_decl = is_decl;
+    }
+
+    pub fn ret_ty(&self) -> Type {
+        self.ret_ty
+    }
+
+    pub fn set_ret_ty(&mut self, ty: Type) {
+        self.ret_ty = ty;
+    }
+
+    pub fn loc(&self) -> Option<&DebugLoc> {
+        self.loc.as_ref()
+    }
+
+    pub fn set_loc(&mut self, loc: Option<DebugLoc>) {
+        self.loc = loc;
+    }
+
+    pub fn comments(&self) -> &Comments {
+        &self.comments
+    }
+
+    pub fn comments_mut(&mut self) -> &mut Comments {
+        &mut self.comments
+    }
+
+    pub fn attrs(&self) -> Attrs {
+        self.attrs
+    }
+
+    pub fn add_attr(&mut self, attr: Attr) {
+        self.attrs.add(attr);
+    }
+
     pub fn bbs(&self) -> &[BasicBlockRef] {
         assert!(!self.is_decl);
         &self.bbs_list[..]
     }
 
+    #[allow(dead_code)]
     pub fn bbs_mut(&mut self) -> &mut [BasicBlockRef] {
         assert!(!self.is_decl);
         &mut self.bbs_list[..]
     }
 
+    #[allow(dead_code)]
     pub fn insert_begin(&mut self, bb: BasicBlockRef) {
         assert!(!self.is_decl);
         self.bbs_list.insert(0, bb);
@@ -63,6 +113,7 @@ impl Function {
         self.bbs_list.insert(self.bb_idx(pos).unwrap(), new_bb);
     }
 
+    #[allow(dead_code)]
     pub fn insert_after(&mut self, new_bb: BasicBlockRef, pos: BasicBlockRef) {
         assert!(!self.is_decl);
         self.bbs_list.insert(self.bb_idx(pos).unwrap() + 1, new_bb);
@@ -0,0 +1,65 @@
+use crate::comments::Comments;
+use crate::value::Value;
+use crate::valueref::GlobalRef;
+
+// Module level array of `len` i64 values
+// Values past the end of `init` are zero, a scalar is an array of length 1
+pub struct Global {
+    val: Value,
+    is_const: bool,
+    len: usize,
+    init: Vec<i64>,
+    comments: Comments,
+}
+
+impl Global {
+    pub fn new(val: Value, is_const: bool, len: usize, init: &[i64]) -> Global {
+        assert!(init.len() <= len);
+        Global {
+            val,
+            is_const,
+            len,
+            init: init.to_vec(),
+            comments: Comments::default(),
+        }
+    }
+
+    pub fn id(&self) -> GlobalRef {
+        self.val.id().raw().into()
+    }
+
+    pub fn val(&self) -> &Value {
+        &self.val
+    }
+
+    pub fn val_mut(&mut self) -> &mut Value {
+        &mut self.val
+    }
+
+    // Read-only data, declared with `.const`
+    pub fn is_const(&self) -> bool {
+        self.is_const
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn init(&self) -> &[i64] {
+        &self.init[..]
+    }
+
+    pub fn comments(&self) -> &Comments {
+        &self.comments
+    }
+
+    pub fn comments_mut(&mut self) -> &mut Comments {
+        &mut self.comments
+    }
+}
+
+impl From<&Global> for GlobalRef {
+    fn from(x: &Global) -> Self {
+        x.id()
+    }
+}
@@ -1,7 +1,7 @@
+use crate::debug_loc::DebugLoc;
+
 use std::fmt;
-use std::fs::File;
-use std::io;
-use std::io::BufRead;
+use std::rc::Rc;
 
 // generic instruction
 pub struct Ins {
@@ -75,7 +75,7 @@ impl Dir {
     }
 
     fn parse(s: &str) -> Dir {
-        if s.is_empty() || s.chars().next().unwrap() != '.' {
+        if !s.starts_with('.') {
             panic!("Invalid directive");
         }
 
@@ -103,7 +103,7 @@ pub enum DeclBody {
 
 impl DeclBody {
     fn parse(s: &str) -> DeclBody {
-        if !s.is_empty() && s.chars().next().unwrap() == '.' {
+        if s.starts_with('.') {
             DeclBody::Dir(Dir::parse(s))
         } else {
             DeclBody::Ins(Ins::parse(s))
@@ -115,6 +115,7 @@ pub struct Decl {
     label_defs: Vec<String>,
     comm_pre: Vec<String>,
     comm_eol: String,
+    loc: Option<DebugLoc>,
 
     body: DeclBody,
 }
@@ -130,6 +131,7 @@ impl Decl {
             label_defs,
             comm_pre,
             comm_eol,
+            loc: None,
             body: DeclBody::Ins(Ins { args }),
         }
     }
@@ -144,6 +146,7 @@ impl Decl {
             label_defs,
             comm_pre,
             comm_eol,
+            loc: None,
             body: DeclBody::Dir(Dir { args }),
         }
     }
@@ -155,19 +158,38 @@ impl Decl {
     pub fn body(&self) -> &DeclBody {
         &self.body
     }
+
+    // Comment lines before the body, label definitions excluded
+    pub fn comm_pre(&self) -> &[String] {
+        &self.comm_pre[..]
+    }
+
+    // Comment at the end of the body line, empty if none
+    pub fn comm_eol(&self) -> &str {
+        &self.comm_eol
+    }
+
+    // Position of the body in the parsed file
+    pub fn loc(&self) -> Option<&DebugLoc> {
+        self.loc.as_ref()
+    }
 }
 
 impl fmt::Display for Decl {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if !self.label_defs.is_empty() {
-            write!(f, "\n")?;
+            writeln!(f)?;
             for label in &self.label_defs {
-                write!(f, "{}:\n", label)?;
+                writeln!(f, "{}:", label)?;
             }
         }
 
+        let indent = match &self.body {
+            DeclBody::Ins(_) => "\t",
+            DeclBody::Dir(_) => "",
+        };
         for comm in &self.comm_pre {
-            write!(f, " ; {}\n", comm)?;
+            writeln!(f, "{}; {}", indent, comm)?;
         }
 
         match &self.body {
@@ -179,7 +201,7 @@ impl fmt::Display for Decl {
             write!(f, " ; {}", self.comm_eol)?;
         }
 
-        write!(f, "\n")
+        writeln!(f)
     }
 }
 
@@ -197,33 +219,41 @@ impl Module {
     }
 
     pub fn parse(path: &str) -> Module {
-        let fis = File::open(path).expect("Failed to open file");
-        let lines = io::BufReader::new(fis).lines();
+        let src = std::fs::read_to_string(path).expect("Failed to read file");
+        Module::parse_str(path, &src)
+    }
+
+    // Parse the code in `src`, located in `path` for the debug locations
+    pub fn parse_str(path: &str, src: &str) -> Module {
+        let file: Rc<str> = Rc::from(path);
 
         let mut decls: Vec<Decl> = Vec::new();
         let mut label_defs: Vec<String> = Vec::new();
         let mut comm_pre: Vec<String> = Vec::new();
 
-        for line in lines {
-            let line = line.expect("Failed to read file");
+        for (line_idx, line) in src.lines().enumerate() {
+            let col = line.len() - line.trim_start().len() + 1;
             let line = line.trim();
             if line.is_empty() {
                 continue;
             }
 
-            if line.chars().next().unwrap() == ';' {
-                comm_pre.push(line[1..].to_string());
+            if let Some(comm) = line.strip_prefix(';') {
+                comm_pre.push(strip_comment(comm).to_string());
                 continue;
             }
 
-            if line.chars().last().unwrap() == ':' {
-                label_defs.push(line[..line.len() - 1].to_string());
+            if let Some(label) = line.strip_suffix(':') {
+                label_defs.push(label.to_string());
                 continue;
             }
 
             let (comm_eol, body) = match line.find(';') {
                 None => (String::new(), DeclBody::parse(line)),
-                Some(p) => (line[p + 1..].to_string(), DeclBody::parse(&line[..p])),
+                Some(p) => (
+                    strip_comment(&line[p + 1..]).to_string(),
+                    DeclBody::parse(line[..p].trim_end()),
+                ),
             };
 
             let mut decl_labels = Vec::new();
@@ -234,6 +264,7 @@ impl Module {
                 label_defs: decl_labels,
                 comm_pre: decl_comms,
                 comm_eol,
+                loc: Some(DebugLoc::new(file.clone(), line_idx + 1, col)),
                 body,
             });
         }
@@ -242,11 +273,16 @@ impl Module {
     }
 }
 
+// Text of a comment, without the space following `;`
+fn strip_comment(comm: &str) -> &str {
+    comm.strip_prefix(' ').unwrap_or(comm)
+}
+
 impl fmt::Display for Module {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for d in &self.decls {
             write!(f, "{}", d)?;
         }
-        write!(f, "\n")
+        writeln!(f)
     }
 }
@@ -1,3 +1,3 @@
 pub trait Indexable {
-    fn to_index(&self) -> usize;
+    fn to_index(&self) -> u64;
 }
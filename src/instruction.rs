@@ -1,19 +1,30 @@
+use crate::comments::Comments;
+use crate::debug_loc::DebugLoc;
+use crate::isa::{InsInfos, Opcode};
+use crate::phi::Phi;
+use crate::types::Type;
 use crate::value::Value;
-use crate::valueref::{BasicBlockRef, InstructionRef, ValueRef, ValueRefEnum};
+use crate::valueref::{BasicBlockRef, InstructionRef, ValueRefEnum};
 
 pub struct Instruction {
     val: Value,
     parent: Option<BasicBlockRef>,
 
-    opname: String,
+    opcode: Opcode,
+    ty: Type,
+    loc: Option<DebugLoc>,
+    comments: Comments,
 }
 
 impl Instruction {
-    pub fn new(val: Value, opname: &str) -> Instruction {
+    pub fn new(val: Value, opcode: Opcode, ty: Type) -> Instruction {
         Instruction {
             val,
             parent: None,
-            opname: opname.to_string(),
+            opcode,
+            ty,
+            loc: None,
+            comments: Comments::default(),
         }
     }
 
@@ -29,8 +40,41 @@ impl Instruction {
         &mut self.val
     }
 
-    pub fn opname(&self) -> &str {
-        &self.opname
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    pub fn opname(&self) -> &'static str {
+        self.opcode.name()
+    }
+
+    pub fn infos(&self) -> &'static InsInfos {
+        self.opcode.infos()
+    }
+
+    // Type of the defined value, void if none
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    pub fn set_ty(&mut self, ty: Type) {
+        self.ty = ty;
+    }
+
+    pub fn loc(&self) -> Option<&DebugLoc> {
+        self.loc.as_ref()
+    }
+
+    pub fn set_loc(&mut self, loc: Option<DebugLoc>) {
+        self.loc = loc;
+    }
+
+    pub fn comments(&self) -> &Comments {
+        &self.comments
+    }
+
+    pub fn comments_mut(&mut self) -> &mut Comments {
+        &mut self.comments
     }
 
     pub fn parent(&self) -> Option<BasicBlockRef> {
@@ -41,14 +85,15 @@ impl Instruction {
         self.parent = new_parent;
     }
 
+    pub fn as_phi(&self) -> Option<Phi<'_>> {
+        Phi::new(self)
+    }
+
     pub fn targets_bbs<'a>(&'a self) -> impl Iterator<Item = BasicBlockRef> + 'a {
         self.val()
             .ops()
             .iter()
-            .filter(|x| match x.to_enum() {
-                ValueRefEnum::BB(_) => true,
-                _ => false,
-            })
+            .filter(|x| matches!(x.to_enum(), ValueRefEnum::BB(_)))
             .map(|x| x.raw().into())
     }
 }
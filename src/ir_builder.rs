@@ -0,0 +1,382 @@
+// Construction of instructions at an insertion point
+//
+// Operands are checked against the ISA schema, and every defined value gets a name
+// unique in the function of the insertion point: the one given with
+// `named`, followed by `.N` if already taken, or the prefix followed by a
+// number (`vN` by default).
+// Names are given by a `NameAllocator`. A pass keeps its own one across
+// builders with `with_names`, it must already hold the names of the function.
+
+use crate::context::Context;
+use crate::debug_loc::DebugLoc;
+use crate::isa::Opcode;
+use crate::names::NameAllocator;
+use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRef};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertPoint {
+    End(BasicBlockRef),
+    Before(InstructionRef),
+    After(InstructionRef),
+}
+
+enum Names<'a> {
+    // Filled with the names of the function of the insertion point
+    Own(NameAllocator),
+    Shared(&'a mut NameAllocator),
+}
+
+pub struct IrBuilder<'a> {
+    ctx: &'a mut Context,
+    point: Option<InsertPoint>,
+    names: Names<'a>,
+    prefix: String,
+    name_hint: Option<String>,
+    loc: Option<DebugLoc>,
+}
+
+impl<'a> IrBuilder<'a> {
+    pub fn new(ctx: &'a mut Context) -> IrBuilder<'a> {
+        IrBuilder::with(ctx, Names::Own(NameAllocator::new()))
+    }
+
+    pub fn with_names(ctx: &'a mut Context, names: &'a mut NameAllocator) -> IrBuilder<'a> {
+        IrBuilder::with(ctx, Names::Shared(names))
+    }
+
+    fn with(ctx: &'a mut Context, names: Names<'a>) -> IrBuilder<'a> {
+        IrBuilder {
+            ctx,
+            point: None,
+            names,
+            prefix: "v".to_string(),
+            name_hint: None,
+            loc: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn ctx(&mut self) -> &mut Context {
+        self.ctx
+    }
+
+    #[allow(dead_code)]
+    pub fn point(&self) -> Option<InsertPoint> {
+        self.point
+    }
+
+    pub fn set_point(&mut self, point: InsertPoint) {
+        let bb = match point {
+            InsertPoint::End(bb) => Some(bb),
+            InsertPoint::Before(ins) | InsertPoint::After(ins) => {
+                ins.own(self.ctx).unwrap().parent()
+            }
+        };
+        let fun = bb.and_then(|bb| bb.own(self.ctx).unwrap().parent());
+        if let (Names::Own(names), Some(fun)) = (&mut self.names, fun) {
+            names.add_values(self.ctx, fun);
+        }
+        self.point = Some(point);
+    }
+
+    pub fn set_insert_end(&mut self, bb: BasicBlockRef) {
+        self.set_point(InsertPoint::End(bb));
+    }
+
+    pub fn set_insert_before(&mut self, ins: InstructionRef) {
+        self.set_point(InsertPoint::Before(ins));
+    }
+
+    #[allow(dead_code)]
+    pub fn set_insert_after(&mut self, ins: InstructionRef) {
+        self.set_point(InsertPoint::After(ins));
+    }
+
+    // Location of the next instructions
+    pub fn set_loc(&mut self, loc: Option<DebugLoc>) {
+        self.loc = loc;
+    }
+
+    // Names of the defined values without a given name are `prefix` followed by a number
+    pub fn set_prefix(&mut self, prefix: &str) {
+        self.prefix = prefix.to_string();
+    }
+
+    // Name of the next defined value
+    pub fn named(&mut self, name: &str) -> &mut Self {
+        self.name_hint = Some(name.to_string());
+        self
+    }
+
+    fn insert(&mut self, opcode: Opcode, is_def: bool, ops: &[ValueRef]) -> InstructionRef {
+        let infos = opcode.infos();
+        if !infos.allows_def(is_def) {
+            panic!("Bad definition for instruction {}", opcode);
+        }
+        if let Err(err) = infos.operands().check(ops) {
+            panic!("Bad operands of {}: {}", opcode, err);
+        }
+        let point = self.point.expect("IrBuilder has no insertion point");
+
+        let name = if is_def {
+            self.new_name()
+        } else {
+            String::new()
+        };
+        let ins = self.ctx.make_ins(&name, opcode, is_def, ops);
+        ins.own_mut(self.ctx).unwrap().set_loc(self.loc.clone());
+        match point {
+            InsertPoint::End(bb) => self.ctx.ins_insert_in(ins, bb),
+            InsertPoint::Before(pos) => self.ctx.ins_insert_before(ins, pos),
+            InsertPoint::After(pos) => {
+                self.ctx.ins_insert_after(ins, pos);
+                self.point = Some(InsertPoint::After(ins));
+            }
+        }
+        ins
+    }
+
+    fn new_name(&mut self) -> String {
+        let names = match &mut self.names {
+            Names::Own(names) => names,
+            Names::Shared(names) => &mut **names,
+        };
+        match self.name_hint.take() {
+            Some(name) => names.fresh(&name),
+            None => names.numbered(&self.prefix),
+        }
+    }
+}
+
+// One method per instruction of the ISA, the passes only use some of them
+#[allow(dead_code)]
+impl IrBuilder<'_> {
+    pub fn const_int(&mut self, val: i64) -> ValueRef {
+        self.ctx.make_const("", val).into()
+    }
+
+    pub fn add(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Add, a, b)
+    }
+
+    pub fn sub(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Sub, a, b)
+    }
+
+    pub fn mul(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Mul, a, b)
+    }
+
+    pub fn div(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Div, a, b)
+    }
+
+    pub fn rem(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Rem, a, b)
+    }
+
+    pub fn neg(&mut self, a: ValueRef) -> InstructionRef {
+        self.insert(Opcode::Neg, true, &[a])
+    }
+
+    pub fn and(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::And, a, b)
+    }
+
+    pub fn or(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Or, a, b)
+    }
+
+    pub fn xor(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Xor, a, b)
+    }
+
+    pub fn shl(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Shl, a, b)
+    }
+
+    pub fn shr(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Shr, a, b)
+    }
+
+    pub fn cmplt(&mut self, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.binop(Opcode::Cmplt, a, b)
+    }
+
+    pub fn select(&mut self, cond: ValueRef, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.insert(Opcode::Select, true, &[cond, a, b])
+    }
+
+    pub fn alloca(&mut self, size: ValueRef) -> InstructionRef {
+        self.insert(Opcode::Alloca, true, &[size])
+    }
+
+    pub fn offset(&mut self, ptr: ValueRef, off: ValueRef) -> InstructionRef {
+        self.insert(Opcode::Offset, true, &[ptr, off])
+    }
+
+    pub fn load(&mut self, ptr: ValueRef) -> InstructionRef {
+        self.insert(Opcode::Load, true, &[ptr])
+    }
+
+    pub fn store(&mut self, val: ValueRef, ptr: ValueRef) -> InstructionRef {
+        self.insert(Opcode::Store, false, &[val, ptr])
+    }
+
+    // Any instruction `opcode %res, a, b` of the ISA
+    pub fn binop(&mut self, opcode: Opcode, a: ValueRef, b: ValueRef) -> InstructionRef {
+        self.insert(opcode, true, &[a, b])
+    }
+
+    // Any instruction of the ISA defining a value, e.g. a copy of another one
+    pub fn def(&mut self, opcode: Opcode, ops: &[ValueRef]) -> InstructionRef {
+        self.insert(opcode, true, ops)
+    }
+
+    pub fn br(&mut self, target: BasicBlockRef) -> InstructionRef {
+        self.insert(Opcode::B, false, &[target.into()])
+    }
+
+    pub fn cond_br(
+        &mut self,
+        cond: ValueRef,
+        if_true: BasicBlockRef,
+        if_false: BasicBlockRef,
+    ) -> InstructionRef {
+        self.insert(Opcode::Bc, false, &[cond, if_true.into(), if_false.into()])
+    }
+
+    pub fn ret(&mut self, val: Option<ValueRef>) -> InstructionRef {
+        match val {
+            Some(val) => self.insert(Opcode::Ret, false, &[val]),
+            None => self.insert(Opcode::Ret, false, &[]),
+        }
+    }
+
+    // Call returning a value
+    pub fn call(&mut self, fun: FunctionRef, args: &[ValueRef]) -> InstructionRef {
+        let mut ops = vec![fun.into()];
+        ops.extend_from_slice(args);
+        self.insert(Opcode::Call, true, &ops)
+    }
+
+    pub fn call_void(&mut self, fun: FunctionRef, args: &[ValueRef]) -> InstructionRef {
+        let mut ops = vec![fun.into()];
+        ops.extend_from_slice(args);
+        self.insert(Opcode::Call, false, &ops)
+    }
+
+    pub fn phi(&mut self, incoming: &[(BasicBlockRef, ValueRef)]) -> InstructionRef {
+        let ops: Vec<ValueRef> = incoming
+            .iter()
+            .flat_map(|(bb, val)| vec![(*bb).into(), *val])
+            .collect();
+        self.insert(Opcode::Phi, true, &ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker;
+    use crate::loader;
+
+    #[test]
+    fn build_loop() {
+        let mut ctx = Context::new();
+        let fun = ctx.make_fun("sum", 1, false);
+        ctx.fun_get_arg_mut(fun, 0).val_mut().rename("n");
+        let n: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let entry = ctx.make_bb("entry");
+        let lp = ctx.make_bb("loop");
+        let end = ctx.make_bb("end");
+        for bb in &[entry, lp, end] {
+            ctx.bb_insert_in(*bb, fun);
+        }
+
+        let mut b = IrBuilder::new(&mut ctx);
+        b.set_insert_end(entry);
+        b.br(lp);
+
+        b.set_insert_end(lp);
+        let zero = b.const_int(0);
+        let one = b.const_int(1);
+        let i = b.named("i").phi(&[(entry, zero)]);
+        let s = b.named("s").phi(&[(entry, zero)]);
+        let s1 = b.add(s.into(), i.into());
+        let i1 = b.named("i").add(i.into(), one);
+        let c = b.cmplt(i1.into(), n);
+        b.cond_br(c.into(), lp, end);
+
+        b.set_insert_end(end);
+        b.ret(Some(s1.into()));
+
+        // Back edges of the phis
+        b.ctx().phi_add_incoming(i, lp, i1.into());
+        b.ctx().phi_add_incoming(s, lp, s1.into());
+
+        // Instructions inserted after the first add
+        b.set_insert_after(s1);
+        let t0 = b.mul(s1.into(), i.into());
+        b.named("n").shl(t0.into(), one);
+
+        checker::check_code(&ctx);
+        assert_eq!(
+            format!("{}", loader::build_gop(&ctx)).trim_start(),
+            "sum:
+.fun i64, %n
+
+entry:
+\tb @loop
+
+loop:
+\tphi %i, @entry, 0, @loop, %i.1
+\tphi %s, @entry, 0, @loop, %v0
+\tadd %v0, %s, %i
+\tmul %v2, %v0, %i
+\tshl %n.1, %v2, 1
+\tadd %i.1, %i, 1
+\tcmplt %v1, %i.1, %n
+\tbc %v1, @loop, @end
+
+end:
+\tret %v0
+
+"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "IrBuilder has no insertion point")]
+    fn build_no_point() {
+        let mut ctx = Context::new();
+        let mut b = IrBuilder::new(&mut ctx);
+        let one = b.const_int(1);
+        b.add(one, one);
+    }
+
+    #[test]
+    fn build_shared_names() {
+        let mut ctx = Context::new();
+        let fun = ctx.make_fun("f", 1, false);
+        ctx.fun_get_arg_mut(fun, 0).val_mut().rename("t.0");
+        let x: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        let bb = ctx.make_bb("B0");
+        ctx.bb_insert_in(bb, fun);
+
+        // Names given by one builder are known to the next one
+        let mut names = NameAllocator::of_values(&ctx, fun);
+        let mut b = IrBuilder::with_names(&mut ctx, &mut names);
+        b.set_prefix("t.");
+        b.set_insert_end(bb);
+        let a = b.add(x, x);
+        let mut b = IrBuilder::with_names(&mut ctx, &mut names);
+        b.set_prefix("t.");
+        b.set_insert_end(bb);
+        let c = b.sub(a.into(), x);
+        b.ret(Some(c.into()));
+
+        assert_eq!(a.own(&ctx).unwrap().val().name(), "t.1");
+        assert_eq!(c.own(&ctx).unwrap().val().name(), "t.2");
+    }
+}
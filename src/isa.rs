@@ -1,35 +1,317 @@
+// Instruction set
+//
+// Every opcode has a schema of its operands and result, used to parse, build,
+// check and print instructions.
+
+use crate::valueref::{ValueRef, ValueRefEnum};
+
 use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Add,
+    Alloca,
+    And,
+    B,
+    Bc,
+    Call,
+    Cmpeq,
+    Cmpge,
+    Cmpgt,
+    Cmple,
+    Cmplt,
+    Cmpne,
+    Cmpuge,
+    Cmpugt,
+    Cmpule,
+    Cmpult,
+    Div,
+    Load,
+    Mul,
+    Neg,
+    Offset,
+    Or,
+    Phi,
+    Rem,
+    Ret,
+    Select,
+    Shl,
+    Shr,
+    Store,
+    Sub,
+    Xor,
+}
+
+impl Opcode {
+    pub fn parse(name: &str) -> Option<Opcode> {
+        ISA::instance().find_ins(name).map(|infos| infos.opcode())
+    }
+
+    pub fn name(self) -> &'static str {
+        self.infos().name()
+    }
+
+    pub fn infos(self) -> &'static InsInfos {
+        ISA::instance().get(self)
+    }
+
+    // Result of the instruction on constant operands, following the
+    // semantics of the ISA table
+    // None if `self` doesn't compute a value from its operands only
+    pub fn eval(self, ops: &[i64]) -> Option<i64> {
+        let (a, b) = (ops[0], ops.get(1).copied().unwrap_or(0));
+        let res = match self {
+            Opcode::Add => a.wrapping_add(b),
+            Opcode::Sub => a.wrapping_sub(b),
+            Opcode::Mul => a.wrapping_mul(b),
+            Opcode::Div if b == 0 => -1,
+            Opcode::Div => a.wrapping_div(b),
+            Opcode::Rem if b == 0 => a,
+            Opcode::Rem => a.wrapping_rem(b),
+            Opcode::Neg => a.wrapping_neg(),
+            Opcode::And => a & b,
+            Opcode::Or => a | b,
+            Opcode::Xor => a ^ b,
+            Opcode::Shl => a.wrapping_shl(b as u32),
+            Opcode::Shr => a.wrapping_shr(b as u32),
+            Opcode::Cmpeq => (a == b) as i64,
+            Opcode::Cmpne => (a != b) as i64,
+            Opcode::Cmplt => (a < b) as i64,
+            Opcode::Cmple => (a <= b) as i64,
+            Opcode::Cmpgt => (a > b) as i64,
+            Opcode::Cmpge => (a >= b) as i64,
+            Opcode::Cmpult => ((a as u64) < (b as u64)) as i64,
+            Opcode::Cmpule => ((a as u64) <= (b as u64)) as i64,
+            Opcode::Cmpugt => ((a as u64) > (b as u64)) as i64,
+            Opcode::Cmpuge => ((a as u64) >= (b as u64)) as i64,
+            Opcode::Select if a != 0 => b,
+            Opcode::Select => ops[2],
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Value, // argument, instruction or constant
+    Block,
+    Fun,
+}
+
+impl OperandKind {
+    pub fn of(v: ValueRef) -> OperandKind {
+        match v.to_enum() {
+            ValueRefEnum::BB(_) => OperandKind::Block,
+            ValueRefEnum::Fun(_) => OperandKind::Fun,
+            _ => OperandKind::Value,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OperandKind::Value => "value",
+            OperandKind::Block => "block",
+            OperandKind::Fun => "function",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefKind {
+    Never,
+    Always,
+    Optional, // calls, depending on the callee
+}
+
+// `fixed` operands, followed by up to `max_groups` repetitions of `group`
+pub struct OperandSchema {
+    fixed: &'static [OperandKind],
+    group: &'static [OperandKind],
+    max_groups: Option<usize>,
+}
+
+impl OperandSchema {
+    // Kind of operand `idx`, None if past the schema
+    pub fn kind(&self, idx: usize) -> Option<OperandKind> {
+        if idx < self.fixed.len() {
+            return Some(self.fixed[idx]);
+        }
+        if self.group.is_empty() {
+            return None;
+        }
+
+        let idx = idx - self.fixed.len();
+        match self.max_groups {
+            Some(max) if idx / self.group.len() >= max => None,
+            _ => Some(self.group[idx % self.group.len()]),
+        }
+    }
+
+    pub fn check_count(&self, count: usize) -> Result<(), String> {
+        let rest = count.checked_sub(self.fixed.len());
+        let ok = match rest {
+            None => false,
+            Some(0) => true,
+            Some(_) if self.group.is_empty() => false,
+            Some(rest) => {
+                rest % self.group.len() == 0
+                    && self
+                        .max_groups
+                        .is_none_or(|max| rest / self.group.len() <= max)
+            }
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(format!("bad number of operands {}", count))
+        }
+    }
+
+    pub fn check(&self, ops: &[ValueRef]) -> Result<(), String> {
+        self.check_count(ops.len())?;
+        for (idx, op) in ops.iter().enumerate() {
+            let (kind, expected) = (OperandKind::of(*op), self.kind(idx).unwrap());
+            if kind != expected {
+                return Err(format!(
+                    "operand {} is a {}, expected a {}",
+                    idx,
+                    kind.name(),
+                    expected.name()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
 
 pub struct InsInfos {
+    opcode: Opcode,
     name: &'static str,
-    is_term: bool, //terminator instruction (ret / jump / branch)
-    is_def: bool,  //define a value (instruction store result in some reg)
+    def: DefKind,
+    operands: OperandSchema,
+    is_term: bool,          // terminator instruction (ret / jump / branch)
+    is_cmp: bool,           // comparison, the result is an i1
+    is_commutative: bool,   // operands of a binary instruction can be swapped
+    has_side_effects: bool, // can't be removed even if its value is unused
+    reads_memory: bool,
 }
 
 impl InsInfos {
+    fn new(
+        opcode: Opcode,
+        name: &'static str,
+        def: DefKind,
+        fixed: &'static [OperandKind],
+    ) -> Self {
+        InsInfos {
+            opcode,
+            name,
+            def,
+            operands: OperandSchema {
+                fixed,
+                group: &[],
+                max_groups: None,
+            },
+            is_term: false,
+            is_cmp: false,
+            is_commutative: false,
+            has_side_effects: false,
+            reads_memory: false,
+        }
+    }
+
+    fn repeat(mut self, group: &'static [OperandKind], max_groups: Option<usize>) -> Self {
+        self.operands.group = group;
+        self.operands.max_groups = max_groups;
+        self
+    }
+
+    fn term(mut self) -> Self {
+        self.is_term = true;
+        self.has_side_effects = true;
+        self
+    }
+
+    fn cmp(mut self) -> Self {
+        self.is_cmp = true;
+        self
+    }
+
+    fn commutative(mut self) -> Self {
+        self.is_commutative = true;
+        self
+    }
+
+    fn side_effects(mut self) -> Self {
+        self.has_side_effects = true;
+        self
+    }
+
+    fn reads(mut self) -> Self {
+        self.reads_memory = true;
+        self
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
     pub fn name(&self) -> &'static str {
-        &self.name
+        self.name
     }
 
-    pub fn is_term(&self, _args: &[String]) -> bool {
-        self.is_term
+    pub fn def(&self) -> DefKind {
+        self.def
     }
 
-    pub fn is_def(&self, args: &[String]) -> bool {
-        if (self.name == "call") {
-            self.is_def_call(args)
-        } else {
-            self.is_def
+    // Returns false if an instruction of this opcode can't have / must have
+    // a result
+    pub fn allows_def(&self, is_def: bool) -> bool {
+        match self.def {
+            DefKind::Never => !is_def,
+            DefKind::Always => is_def,
+            DefKind::Optional => true,
         }
     }
 
-    fn is_def_call(&self, args: &[String]) -> bool {
-        return args[1].chars().next().unwrap() == '%';
+    pub fn operands(&self) -> &OperandSchema {
+        &self.operands
+    }
+
+    pub fn is_term(&self) -> bool {
+        self.is_term
+    }
+
+    pub fn is_cmp(&self) -> bool {
+        self.is_cmp
+    }
+
+    pub fn is_commutative(&self) -> bool {
+        self.is_commutative
+    }
+
+    pub fn has_side_effects(&self) -> bool {
+        self.has_side_effects
+    }
+
+    // The result depends on the content of memory
+    #[allow(dead_code)]
+    pub fn reads_memory(&self) -> bool {
+        self.reads_memory
     }
 }
 
 pub struct ISA {
-    ins_infos: HashMap<&'static str, InsInfos>,
+    ins_infos: HashMap<Opcode, InsInfos>,
+    names: HashMap<&'static str, Opcode>,
 }
 
 impl ISA {
@@ -38,39 +320,162 @@ impl ISA {
     }
 
     pub fn find_ins(&self, name: &str) -> Option<&InsInfos> {
-        self.ins_infos.get(name)
+        self.names.get(name).map(|opcode| self.get(*opcode))
+    }
+
+    pub fn get(&self, opcode: Opcode) -> &InsInfos {
+        &self.ins_infos[&opcode]
     }
 
     fn new() -> ISA {
         let mut res = ISA {
             ins_infos: HashMap::new(),
+            names: HashMap::new(),
         };
         res.setup();
         res
     }
 
-    fn add_ins(&mut self, name: &'static str, is_term: bool, is_def: bool) {
-        let infos = InsInfos {
-            name,
-            is_term,
-            is_def,
-        };
-        self.ins_infos.insert(name, infos);
+    fn add_ins(&mut self, infos: InsInfos) {
+        self.names.insert(infos.name, infos.opcode);
+        self.ins_infos.insert(infos.opcode, infos);
     }
 
     fn setup(&mut self) {
-        self.add_ins("add", /*is_term=*/ false, /*is_def=*/ true);
-        self.add_ins("b", /*is_term=*/ true, /*is_def=*/ false);
-        self.add_ins("bc", /*is_term=*/ true, /*is_def=*/ false);
-        self.add_ins("call", /*is_term=*/ false, /*is_def=*/ false);
-        self.add_ins("cmplt", /*is_term=*/ false, /*is_def=*/ true);
-        self.add_ins("mul", /*is_term=*/ false, /*is_def=*/ true);
-        self.add_ins("phi", /*is_term=*/ false, /*is_def=*/ true);
-        self.add_ins("ret", /*is_term=*/ true, /*is_def=*/ false);
-        self.add_ins("sub", /*is_term=*/ false, /*is_def=*/ true);
+        use DefKind::{Always, Never, Optional};
+        use OperandKind::{Block, Fun, Value};
+        const BINARY: &[OperandKind] = &[Value, Value];
+
+        // Arithmetic is on 64 bits two's complement integers and wraps on
+        // overflow
+        self.add_ins(InsInfos::new(Opcode::Add, "add", Always, BINARY).commutative());
+        self.add_ins(InsInfos::new(Opcode::Sub, "sub", Always, BINARY));
+        self.add_ins(InsInfos::new(Opcode::Mul, "mul", Always, BINARY).commutative());
+        // Signed division rounding toward zero, `rem` has the sign of the
+        // dividend: `div x, 0` is -1 and `rem x, 0` is x, `div MIN, -1` is
+        // MIN and `rem MIN, -1` is 0
+        self.add_ins(InsInfos::new(Opcode::Div, "div", Always, BINARY));
+        self.add_ins(InsInfos::new(Opcode::Rem, "rem", Always, BINARY));
+        self.add_ins(InsInfos::new(Opcode::Neg, "neg", Always, &[Value]));
+
+        // Bitwise operations, also logical operations on i1 values
+        self.add_ins(InsInfos::new(Opcode::And, "and", Always, BINARY).commutative());
+        self.add_ins(InsInfos::new(Opcode::Or, "or", Always, BINARY).commutative());
+        self.add_ins(InsInfos::new(Opcode::Xor, "xor", Always, BINARY).commutative());
+        // Shift amounts are taken modulo 64, `shr` is an arithmetic shift
+        self.add_ins(InsInfos::new(Opcode::Shl, "shl", Always, BINARY));
+        self.add_ins(InsInfos::new(Opcode::Shr, "shr", Always, BINARY));
+
+        // Comparisons, signed unless prefixed by `u`
+        for (opcode, name) in [(Opcode::Cmpeq, "cmpeq"), (Opcode::Cmpne, "cmpne")] {
+            self.add_ins(
+                InsInfos::new(opcode, name, Always, BINARY)
+                    .cmp()
+                    .commutative(),
+            );
+        }
+        for (opcode, name) in [
+            (Opcode::Cmplt, "cmplt"),
+            (Opcode::Cmple, "cmple"),
+            (Opcode::Cmpgt, "cmpgt"),
+            (Opcode::Cmpge, "cmpge"),
+            (Opcode::Cmpult, "cmpult"),
+            (Opcode::Cmpule, "cmpule"),
+            (Opcode::Cmpugt, "cmpugt"),
+            (Opcode::Cmpuge, "cmpuge"),
+        ] {
+            self.add_ins(InsInfos::new(opcode, name, Always, BINARY).cmp());
+        }
+        // `select %res, c, a, b` is a if the i1 c is true, else b
+        self.add_ins(InsInfos::new(
+            Opcode::Select,
+            "select",
+            Always,
+            &[Value, Value, Value],
+        ));
+
+        // Memory is byte addressed, `load` and `store` access i64 values
+        // `alloca %p, n` allocates n bytes in the frame of the function
+        // `offset %q, p, n` is the address n bytes after p
+        self.add_ins(InsInfos::new(Opcode::Alloca, "alloca", Always, &[Value]));
+        self.add_ins(InsInfos::new(Opcode::Offset, "offset", Always, BINARY));
+        self.add_ins(InsInfos::new(Opcode::Load, "load", Always, &[Value]).reads());
+        // `store v, p` writes v at address p
+        self.add_ins(InsInfos::new(Opcode::Store, "store", Never, BINARY).side_effects());
+
+        self.add_ins(InsInfos::new(Opcode::B, "b", Never, &[Block]).term());
+        self.add_ins(InsInfos::new(Opcode::Bc, "bc", Never, &[Value, Block, Block]).term());
+        self.add_ins(
+            InsInfos::new(Opcode::Call, "call", Optional, &[Fun])
+                .repeat(&[Value], None)
+                .side_effects()
+                .reads(),
+        );
+        // Pairs of incoming block and value
+        self.add_ins(InsInfos::new(Opcode::Phi, "phi", Always, &[]).repeat(&[Block, Value], None));
+        self.add_ins(
+            InsInfos::new(Opcode::Ret, "ret", Never, &[])
+                .repeat(&[Value], Some(1))
+                .term(),
+        );
     }
 }
 
 lazy_static! {
     static ref ISA_INSTANCE: ISA = ISA::new();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isa_schema() {
+        use OperandKind::{Block, Fun, Value};
+
+        assert_eq!(Opcode::parse("phi"), Some(Opcode::Phi));
+        assert_eq!(Opcode::parse("div"), Some(Opcode::Div));
+        assert_eq!(Opcode::parse("udiv"), None);
+        assert_eq!(Opcode::Cmplt.to_string(), "cmplt");
+
+        let phi = Opcode::Phi.infos().operands();
+        assert_eq!(phi.kind(2), Some(Block));
+        assert_eq!(phi.kind(3), Some(Value));
+        assert!(phi.check_count(4).is_ok());
+        assert!(phi.check_count(3).is_err());
+
+        let ret = Opcode::Ret.infos().operands();
+        assert!(ret.check_count(0).is_ok());
+        assert!(ret.check_count(1).is_ok());
+        assert!(ret.check_count(2).is_err());
+        assert_eq!(ret.kind(1), None);
+
+        let call = Opcode::Call.infos().operands();
+        assert_eq!(call.kind(0), Some(Fun));
+        assert_eq!(call.kind(3), Some(Value));
+        assert!(call.check_count(0).is_err());
+        assert!(Opcode::Call.infos().allows_def(false));
+        assert!(!Opcode::Add.infos().allows_def(false));
+        assert!(Opcode::Add.infos().is_commutative());
+        assert!(!Opcode::Sub.infos().is_commutative());
+    }
+
+    #[test]
+    fn isa_eval() {
+        assert_eq!(Opcode::Add.eval(&[i64::MAX, 1]), Some(i64::MIN));
+        assert_eq!(Opcode::Div.eval(&[-7, 2]), Some(-3));
+        assert_eq!(Opcode::Rem.eval(&[-7, 2]), Some(-1));
+        assert_eq!(Opcode::Div.eval(&[7, 0]), Some(-1));
+        assert_eq!(Opcode::Rem.eval(&[7, 0]), Some(7));
+        assert_eq!(Opcode::Div.eval(&[i64::MIN, -1]), Some(i64::MIN));
+        assert_eq!(Opcode::Rem.eval(&[i64::MIN, -1]), Some(0));
+        assert_eq!(Opcode::Neg.eval(&[i64::MIN]), Some(i64::MIN));
+        assert_eq!(Opcode::Shl.eval(&[1, 65]), Some(2));
+        assert_eq!(Opcode::Shr.eval(&[-8, 1]), Some(-4));
+        assert_eq!(Opcode::Cmplt.eval(&[-1, 0]), Some(1));
+        assert_eq!(Opcode::Cmpult.eval(&[-1, 0]), Some(0));
+        assert_eq!(Opcode::Cmpuge.eval(&[-1, 0]), Some(1));
+        assert_eq!(Opcode::Select.eval(&[0, 1, 2]), Some(2));
+        assert_eq!(Opcode::Phi.eval(&[0, 1]), None);
+    }
+}
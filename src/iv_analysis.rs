@@ -0,0 +1,334 @@
+// Induction variables and region constants
+// Engineering a Compiler, second edition, section 10.7.2
+//
+// An induction variable is an SCC of the SSA graph made only of phi, add
+// and sub, whose operands outside the SCC are region constants of the loop.
+// A loop is identified by its header block, the block of the SCC member
+// with the lowest reverse postorder number (the header phi).
+
+use crate::cfg::CFG;
+use crate::context::Context;
+use crate::dom_tree::DomTree;
+use crate::isa::Opcode;
+use crate::ssa_graph::SSAGraph;
+use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRef, ValueRefEnum};
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueClass {
+    IV {
+        header: InstructionRef,
+        lp: BasicBlockRef,
+    },
+    RegionConst,
+    Other,
+}
+
+pub struct IVAnalysis {
+    fun: FunctionRef,
+    cfg: CFG,
+    dom: DomTree,
+    rpo_pos: HashMap<BasicBlockRef, usize>,
+    loops: Vec<BasicBlockRef>,
+    sccs: Vec<Vec<InstructionRef>>,
+
+    // Header phi of every induction variable
+    headers: HashMap<InstructionRef, InstructionRef>,
+
+    // Why the members of the other cycles are not induction variables
+    rejects: HashMap<InstructionRef, &'static str>,
+}
+
+impl IVAnalysis {
+    pub fn new(ctx: &Context, fun: FunctionRef) -> IVAnalysis {
+        let cfg = CFG::new(ctx, fun);
+        let dom = DomTree::new(ctx, &cfg, fun);
+        let rpo = cfg.rev_postorder();
+        let rpo_pos = rpo.iter().enumerate().map(|(idx, bb)| (*bb, idx)).collect();
+
+        // Loop headers are targets of back edges
+        let loops = rpo
+            .iter()
+            .filter(|bb| cfg.preds(**bb).any(|pred| dom.dominates(**bb, pred)))
+            .copied()
+            .collect();
+
+        let mut res = IVAnalysis {
+            fun,
+            cfg,
+            dom,
+            rpo_pos,
+            loops,
+            sccs: SSAGraph::new(ctx, fun).sccs(),
+            headers: HashMap::new(),
+            rejects: HashMap::new(),
+        };
+        res.build(ctx);
+        res
+    }
+
+    #[allow(dead_code)]
+    pub fn fun(&self) -> FunctionRef {
+        self.fun
+    }
+
+    #[allow(dead_code)]
+    pub fn cfg(&self) -> &CFG {
+        &self.cfg
+    }
+
+    pub fn dom(&self) -> &DomTree {
+        &self.dom
+    }
+
+    pub fn loops(&self) -> &[BasicBlockRef] {
+        &self.loops[..]
+    }
+
+    // Blocks of the loop of header `lp`: the blocks reaching a back edge to
+    // `lp` without going through `lp`
+    pub fn loop_blocks(&self, lp: BasicBlockRef) -> HashSet<BasicBlockRef> {
+        let mut res = HashSet::from([lp]);
+        let mut stack: Vec<BasicBlockRef> = self
+            .cfg
+            .preds(lp)
+            .filter(|pred| self.dom.dominates(lp, *pred))
+            .collect();
+        while let Some(bb) = stack.pop() {
+            if res.insert(bb) {
+                stack.extend(self.cfg.preds(bb));
+            }
+        }
+        res
+    }
+
+    // SCCs of the SSA graph, operands before users
+    pub fn sccs(&self) -> &[Vec<InstructionRef>] {
+        &self.sccs[..]
+    }
+
+    pub fn iv_header(&self, v: ValueRef) -> Option<InstructionRef> {
+        match v.to_enum() {
+            ValueRefEnum::Ins(i) => self.headers.get(&i).copied(),
+            _ => None,
+        }
+    }
+
+    pub fn is_iv(&self, v: ValueRef) -> bool {
+        self.iv_header(v).is_some()
+    }
+
+    // Header block of the loop of an induction variable
+    #[allow(dead_code)]
+    pub fn iv_loop(&self, ctx: &Context, v: ValueRef) -> Option<BasicBlockRef> {
+        let header = self.iv_header(v)?;
+        header.own(ctx).unwrap().parent()
+    }
+
+    // Reason why a value in a cycle of the SSA graph is not an induction variable
+    pub fn reject_reason(&self, v: ValueRef) -> Option<&'static str> {
+        match v.to_enum() {
+            ValueRefEnum::Ins(i) => self.rejects.get(&i).copied(),
+            _ => None,
+        }
+    }
+
+    // Record a new induction variable created by a transformation
+    pub fn add_iv(&mut self, ins: InstructionRef, header: InstructionRef) {
+        self.headers.insert(ins, header);
+    }
+
+    pub fn is_region_const(&self, ctx: &Context, v: ValueRef, lp: BasicBlockRef) -> bool {
+        match v.to_enum() {
+            ValueRefEnum::Const(_) | ValueRefEnum::Arg(_) | ValueRefEnum::Global(_) => true,
+            ValueRefEnum::Ins(i) => match i.own(ctx).unwrap().parent() {
+                Some(bb) => self.dom.strictly_dominates(bb, lp),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    // Classify `v` relative to loop `lp`
+    // An induction variable of an enclosing loop is a region constant of `lp`
+    pub fn classify(&self, ctx: &Context, v: ValueRef, lp: BasicBlockRef) -> ValueClass {
+        if let Some(header) = self.iv_header(v) {
+            let iv_lp = header.own(ctx).unwrap().parent().unwrap();
+            if iv_lp == lp || !self.is_region_const(ctx, v, lp) {
+                return ValueClass::IV { header, lp: iv_lp };
+            }
+        }
+
+        if self.is_region_const(ctx, v, lp) {
+            ValueClass::RegionConst
+        } else {
+            ValueClass::Other
+        }
+    }
+
+    pub fn dump<T: std::io::Write>(&self, ctx: &Context, os: &mut T) -> std::io::Result<()> {
+        let fun = self.fun.own(ctx).unwrap();
+        let mut vals: Vec<ValueRef> = fun.args().iter().map(|arg| (*arg).into()).collect();
+        for bb in fun.bbs() {
+            for ins in bb.own(ctx).unwrap().ins() {
+                if ins.own(ctx).unwrap().val().is_def() {
+                    vals.push((*ins).into());
+                }
+            }
+        }
+
+        writeln!(os, "{}:", fun.val().name())?;
+        for lp in &self.loops {
+            writeln!(os, "  loop @{}", lp.own(ctx).unwrap().val().name())?;
+            for v in &vals {
+                let name = v.own(ctx).unwrap().name();
+                match self.classify(ctx, *v, *lp) {
+                    ValueClass::IV { header, lp } => writeln!(
+                        os,
+                        "    %{}: iv (header %{}, loop @{})",
+                        name,
+                        header.own(ctx).unwrap().val().name(),
+                        lp.own(ctx).unwrap().val().name()
+                    )?,
+                    ValueClass::RegionConst => writeln!(os, "    %{}: rc", name)?,
+                    ValueClass::Other => writeln!(os, "    %{}: -", name)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build(&mut self, ctx: &Context) {
+        let sccs = std::mem::take(&mut self.sccs);
+        for scc in &sccs {
+            if scc.len() < 2 {
+                continue;
+            }
+            match self.classify_iv(ctx, scc) {
+                Ok(header) => {
+                    for n in scc {
+                        self.headers.insert(*n, header);
+                    }
+                }
+                Err(reason) => {
+                    for n in scc {
+                        self.rejects.insert(*n, reason);
+                    }
+                }
+            }
+        }
+        self.sccs = sccs;
+    }
+
+    // Returns the header phi if the SCC is an induction variable
+    fn classify_iv(
+        &self,
+        ctx: &Context,
+        scc: &[InstructionRef],
+    ) -> Result<InstructionRef, &'static str> {
+        let header = *scc
+            .iter()
+            .min_by_key(|n| self.ins_rpo_pos(ctx, **n))
+            .unwrap();
+        let header_obj = header.own(ctx).unwrap();
+        let lp = header_obj.parent().unwrap();
+        if header_obj.opcode() != Opcode::Phi || lp == self.dom.root() {
+            return Err("IV cycle has no loop header phi");
+        }
+
+        for n in scc {
+            let n = n.own(ctx).unwrap();
+            match n.opcode() {
+                Opcode::Phi => {}
+                // `iv + iv` doubles the IV at each iteration
+                Opcode::Add if in_scc_count(scc, n.val().ops()) < 2 => {}
+                // `rc - iv` negates the IV at each iteration
+                Opcode::Sub if !scc.iter().any(|x| n.val().ops()[1] == (*x).into()) => {}
+                _ => return Err("IV has non-linear update"),
+            }
+        }
+
+        for n in scc {
+            for op in n.own(ctx).unwrap().val().ops() {
+                let in_scc = match op.to_enum() {
+                    ValueRefEnum::Ins(o) => scc.contains(&o),
+                    ValueRefEnum::BB(_) => true,
+                    _ => false,
+                };
+                if !in_scc && !self.is_region_const(ctx, *op, lp) {
+                    return Err("IV updated by a non region constant");
+                }
+            }
+        }
+
+        Ok(header)
+    }
+
+    fn ins_rpo_pos(&self, ctx: &Context, ins: InstructionRef) -> (usize, usize) {
+        let bb = ins.own(ctx).unwrap().parent().unwrap();
+        let pos = bb
+            .own(ctx)
+            .unwrap()
+            .ins()
+            .iter()
+            .position(|x| *x == ins)
+            .unwrap();
+        (self.rpo_pos[&bb], pos)
+    }
+}
+
+fn in_scc_count(scc: &[InstructionRef], ops: &[ValueRef]) -> usize {
+    ops.iter()
+        .filter(|op| scc.iter().any(|x| **op == (*x).into()))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gop;
+    use crate::loader;
+    use crate::test_utils::find_path;
+
+    fn dump_file(path: &str) -> String {
+        let path = find_path(path);
+
+        let mut ctx = Context::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        let fun = ctx.funs().next().unwrap();
+        let mut os = vec![];
+        IVAnalysis::new(&ctx, fun).dump(&ctx, &mut os).unwrap();
+        String::from_utf8(os).unwrap()
+    }
+
+    #[test]
+    fn iv_fact_iter() {
+        let res = dump_file("examples/fact_iter.ir");
+        assert_eq!(
+            res,
+            "fact:
+  loop @loop
+    %x: rc
+    %c: rc
+    %r: -
+    %i: iv (header %i, loop @loop)
+    %r1: -
+    %i1: iv (header %i, loop @loop)
+    %t: -
+    %res: -
+"
+        );
+    }
+
+    #[test]
+    fn iv_cycle1() {
+        let res = dump_file("examples/cycle1.ir");
+        assert!(res.contains("  loop @B1\n"));
+        assert!(res.contains("    %i: iv (header %i, loop @B1)\n"));
+        assert!(res.contains("    %i2: iv (header %i, loop @B1)\n"));
+        assert!(res.contains("    %r: -\n"));
+        assert!(res.contains("    %t2: -\n"));
+    }
+}
@@ -1,8 +1,17 @@
-use crate::checker;
+use crate::attrs::Attr;
+use crate::basicblock::BasicBlock;
+use crate::comments::Comments;
 use crate::context::Context;
+use crate::debug_loc::{loc_prefix, DebugLoc};
+use crate::function::Function;
 use crate::gop;
-use crate::isa::ISA;
-use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRef, ValueRefEnum};
+use crate::instruction::Instruction;
+use crate::isa::{DefKind, InsInfos, Opcode, OperandKind};
+use crate::types::Type;
+use crate::valueref::{
+    BasicBlockRef, FunctionRef, GlobalRef, InstructionRef, ValueRef, ValueRefEnum,
+};
+use crate::visitor::{self, Visitor};
 
 use std::collections::HashMap;
 
@@ -10,10 +19,15 @@ struct CodeBuilder {
     act_fun: Option<FunctionRef>,
     act_bb: Option<BasicBlockRef>,
     funs_map: HashMap<String, FunctionRef>,
+    globals_map: HashMap<String, GlobalRef>,
     vars_map: HashMap<String, ValueRef>,
     bbs_map: HashMap<String, BasicBlockRef>,
     ins_list: Vec<(InstructionRef, Vec<String>)>,
     mock_var: Option<ValueRef>,
+    loc: Option<DebugLoc>, // of the declaration being handled
+
+    // Function given attributes by `.attr`: the one of the previous `.fun` or `.decl`
+    attr_target: Option<FunctionRef>,
 }
 
 impl CodeBuilder {
@@ -22,35 +36,61 @@ impl CodeBuilder {
             act_fun: None,
             act_bb: None,
             funs_map: HashMap::new(),
+            globals_map: HashMap::new(),
             vars_map: HashMap::new(),
             bbs_map: HashMap::new(),
             ins_list: vec![],
             mock_var: None,
+            loc: None,
+            attr_target: None,
         }
     }
 
     pub fn run(&mut self, ctx: &mut Context, gmod: &gop::Module) {
         self.mock_var = Some(ctx.make_const("", 42).into());
 
+        // Globals and functions can be referenced from any function, even
+        // before their definition
         for decl in gmod.decls() {
             if let gop::DeclBody::Dir(d) = decl.body() {
-                let args = d.args();
-                if args[0] != "fun" {
-                    panic!("Unknown directive");
+                self.loc = decl.loc().cloned();
+                match &d.args()[0][..] {
+                    "global" | "const" => self.handle_global_dir(ctx, decl, d),
+                    "decl" => self.handle_decl_dir(ctx, decl, d),
+                    "fun" => self.declare_fun_dir(ctx, decl, d),
+                    _ => {}
                 }
+            }
+        }
 
-                if decl.label_defs().len() != 1 {
-                    panic!("Missing function name");
+        for decl in gmod.decls() {
+            self.loc = decl.loc().cloned();
+            if let gop::DeclBody::Dir(d) = decl.body() {
+                match &d.args()[0][..] {
+                    "fun" => {}
+                    "global" | "const" => {
+                        self.attr_target = None;
+                        continue;
+                    }
+                    "decl" => {
+                        self.attr_target = Some(self.funs_map[decl_name(d)]);
+                        continue;
+                    }
+                    "attr" => {
+                        self.handle_attr_dir(ctx, d);
+                        continue;
+                    }
+                    dir => self.error(format!("Unknown directive {}", dir)),
                 }
 
-                self.handle_fun_dir(ctx, decl, d);
+                self.handle_fun_dir(ctx, decl);
             } else if let gop::DeclBody::Ins(ins) = decl.body() {
                 let label = if decl.label_defs().is_empty() {
                     None
                 } else {
                     Some(&decl.label_defs()[0][..])
                 };
-                self.handle_ins(ctx, ins.args(), label);
+                self.handle_ins(ctx, ins.args(), label, decl_comments(decl));
             } else {
                 unreachable!();
             }
@@ -59,61 +99,229 @@ impl CodeBuilder {
         self.finish_fun(ctx);
 
         /*
-            if self.mock_var.unwrap().own(ctx).unwrap().users().len() != 0 {
+            if self.mock_var.unwrap().own(ctx).unwrap().has_uses() {
                 panic!("mock var still in use");
             }
             ctx.erase_const(self.mock_var.unwrap().raw().into());
         */
     }
 
-    fn handle_fun_dir(&mut self, ctx: &mut Context, decl: &gop::Decl, d: &gop::Dir) {
-        if self.act_fun.is_some() {
-            self.finish_fun(ctx);
+    // `.global i64, init` or `.global i64[len], init...`, same for `.const`
+    fn handle_global_dir(&mut self, ctx: &mut Context, decl: &gop::Decl, d: &gop::Dir) {
+        if decl.label_defs().len() != 1 {
+            self.error("Missing global name".to_string());
+        }
+        let name = &decl.label_defs()[0];
+        if self.globals_map.contains_key(name) {
+            self.error(format!("Duplicate global {}", name));
         }
 
         let args = d.args();
+        let ty = args.get(1).map(|ty| &ty[..]).unwrap_or("");
+        let len = match ty.strip_prefix("i64") {
+            Some("") => 1,
+            Some(len) => len
+                .strip_prefix('[')
+                .and_then(|len| len.strip_suffix(']'))
+                .and_then(|len| len.parse().ok())
+                .unwrap_or_else(|| {
+                    self.error(format!("Bad array length {} of global {}", len, name))
+                }),
+            None => self.error(format!("Unknown type {} of global {}", ty, name)),
+        };
+        let init: Vec<i64> = args[2..]
+            .iter()
+            .map(|v| {
+                v.parse()
+                    .unwrap_or_else(|_| self.error(format!("invalid number argument {}", v)))
+            })
+            .collect();
+        if init.len() > len {
+            self.error(format!("Too many initializers for global {}", name));
+        }
 
-        self.vars_map.clear();
-        self.bbs_map.clear();
-        self.ins_list.clear();
+        let global = ctx.make_global(name, args[0] == "const", len, &init);
+        *global.own_mut(ctx).unwrap().comments_mut() = decl_comments(decl);
+        self.globals_map.insert(name.to_string(), global);
+    }
+
+    // `.decl name, argtypes...` or `.decl name:ret, argtypes...`, returning i64 by default
+    fn handle_decl_dir(&mut self, ctx: &mut Context, decl: &gop::Decl, d: &gop::Dir) {
+        if !decl.label_defs().is_empty() {
+            self.error("Unexpected label before .decl".to_string());
+        }
+        let args = d.args();
+        let (name, ret_ty) = match args.get(1).map(|arg| arg.split_once(':')) {
+            None => self.error("Missing function name".to_string()),
+            Some(Some((name, ty))) => (name, self.parse_type(ty, name)),
+            Some(None) => (&args[1][..], Type::I64),
+        };
+        let args_tys: Vec<Type> = args[2..]
+            .iter()
+            .map(|ty| self.parse_type(ty, name))
+            .collect();
+        if self
+            .check_redecl(ctx, name, ret_ty, &args_tys, true)
+            .is_some()
+        {
+            // Already defined by `.fun`
+            return;
+        }
+
+        let fun = declare_fun(ctx, name, ret_ty, &args_tys);
+        let fun_obj = fun.own_mut(ctx).unwrap();
+        fun_obj.set_loc(self.loc.clone());
+        *fun_obj.comments_mut() = decl_comments(decl);
+        self.funs_map.insert(name.to_string(), fun);
+    }
+
+    // `.attr attr...`, for the function of the previous `.fun` or `.decl`
+    fn handle_attr_dir(&mut self, ctx: &mut Context, d: &gop::Dir) {
+        let fun = self
+            .attr_target
+            .unwrap_or_else(|| self.error("Attributes must follow .fun or .decl".to_string()));
+        for name in &d.args()[1..] {
+            let attr = Attr::parse(name)
+                .unwrap_or_else(|| self.error(format!("Unknown attribute {}", name)));
+            fun.own_mut(ctx).unwrap().add_attr(attr);
+        }
+    }
+
+    fn parse_type(&self, ty: &str, name: &str) -> Type {
+        Type::parse(ty).unwrap_or_else(|| self.error(format!("Unknown type {} of {}", ty, name)))
+    }
+
+    // Signature of `.fun ret, args...`, the function is created before loading
+    // any body so that calls can reference functions defined later
+    // A `.decl` of the same function is turned into this definition
+    fn declare_fun_dir(&mut self, ctx: &mut Context, decl: &gop::Decl, d: &gop::Dir) {
+        if decl.label_defs().len() != 1 {
+            self.error("Missing function name".to_string());
+        }
         let fun_name = &decl.label_defs()[0];
+        let args = d.args();
 
-        let args_count = args.len() - 2;
-        let fun = ctx.make_fun(&fun_name, args_count, false);
+        let ret_ty = Type::parse(&args[1]).unwrap_or_else(|| {
+            self.error(format!("Unknown return type {} of {}", args[1], fun_name))
+        });
+        // `%name` or `%name:type`
+        let fun_args: Vec<(&str, Type)> = args[2..]
+            .iter()
+            .map(|arg| match arg.split_once(':') {
+                Some((name, ty)) => (
+                    &name[1..],
+                    Type::parse(ty)
+                        .unwrap_or_else(|| self.error(format!("Unknown type {} of {}", ty, name))),
+                ),
+                None => (&arg[1..], Type::I64),
+            })
+            .collect();
+        let args_tys: Vec<Type> = fun_args.iter().map(|(_, ty)| *ty).collect();
+
+        let fun = match self.check_redecl(ctx, fun_name, ret_ty, &args_tys, false) {
+            Some(fun) => fun,
+            None => declare_fun(ctx, fun_name, ret_ty, &args_tys),
+        };
+        let fun_obj = fun.own_mut(ctx).unwrap();
+        fun_obj.set_decl(false);
+        fun_obj.set_loc(self.loc.clone());
+        *fun_obj.comments_mut() = decl_comments(decl);
         self.funs_map.insert(fun_name.to_string(), fun);
+
         let args_ids = fun.own(ctx).unwrap().args().to_vec();
-        for (idx, arg) in args_ids.iter().enumerate() {
-            let arg_name = &args[2 + idx];
-            arg.own_mut(ctx).unwrap().val_mut().rename(&arg_name[1..]);
-            self.vars_map
-                .insert(arg_name[1..].to_string(), (*arg).into());
+        for (arg, (arg_name, _)) in args_ids.iter().zip(fun_args) {
+            arg.own_mut(ctx).unwrap().val_mut().rename(arg_name);
+        }
+    }
+
+    // Function already declared as `name`, which must have the same signature
+    // Only one `.decl` and one `.fun` are allowed for a function
+    fn check_redecl(
+        &self,
+        ctx: &Context,
+        name: &str,
+        ret_ty: Type,
+        args_tys: &[Type],
+        is_decl: bool,
+    ) -> Option<FunctionRef> {
+        let fun = *self.funs_map.get(name)?;
+        let fun_obj = fun.own(ctx).unwrap();
+        if fun_obj.is_decl() == is_decl {
+            self.error(format!("Duplicate function {}", name));
+        }
+
+        let fun_tys: Vec<Type> = fun_obj
+            .args()
+            .iter()
+            .map(|arg| arg.own(ctx).unwrap().ty())
+            .collect();
+        if fun_obj.ret_ty() != ret_ty || fun_tys != args_tys {
+            self.error(format!(
+                "Function {} redeclared with a different signature",
+                name
+            ));
+        }
+        Some(fun)
+    }
+
+    // Start the body of a function declared by `declare_fun_dir`
+    fn handle_fun_dir(&mut self, ctx: &mut Context, decl: &gop::Decl) {
+        if self.act_fun.is_some() {
+            self.finish_fun(ctx);
+        }
+
+        self.vars_map.clear();
+        self.bbs_map.clear();
+        self.ins_list.clear();
+
+        let fun = self.funs_map[&decl.label_defs()[0]];
+        for arg in fun.own(ctx).unwrap().args() {
+            let arg_name = arg.own(ctx).unwrap().val().name();
+            self.vars_map.insert(arg_name.to_string(), (*arg).into());
         }
 
         self.act_fun = Some(fun);
+        self.attr_target = Some(fun);
     }
 
-    fn handle_ins(&mut self, ctx: &mut Context, args: &[String], label: Option<&str>) {
-        let opname = &args[0][..];
-        let infos = ISA::instance()
-            .find_ins(opname)
-            .expect("unknown instruction");
-        let is_def = infos.is_def(args);
+    fn handle_ins(
+        &mut self,
+        ctx: &mut Context,
+        args: &[String],
+        label: Option<&str>,
+        comments: Comments,
+    ) {
+        self.attr_target = None;
+        let opcode = Opcode::parse(&args[0])
+            .unwrap_or_else(|| self.error(format!("Unknown instruction {}", args[0])));
+        let infos = opcode.infos();
+        let is_def = is_def(infos, args);
         let def_name = if is_def { &args[1][1..] } else { "" };
         let rest_args = if is_def { &args[2..] } else { &args[1..] };
+        if let Err(err) = infos.operands().check_count(rest_args.len()) {
+            self.error(format!("Bad operands of {}: {}", args[0], err));
+        }
 
         if self.act_bb.is_none() {
-            let bb = ctx.make_bb(label.unwrap());
+            let label =
+                label.unwrap_or_else(|| self.error("Missing label of basic block".to_string()));
+            let bb = ctx.make_bb(label);
+            bb.own_mut(ctx).unwrap().set_loc(self.loc.clone());
             self.act_bb = Some(bb);
             ctx.bb_insert_in(bb, self.act_fun.unwrap());
-            self.bbs_map.insert(label.unwrap().to_string(), bb);
+            self.bbs_map.insert(label.to_string(), bb);
         }
 
         let vargs: Vec<ValueRef> = rest_args
             .iter()
-            .map(|arg| self.handle_arg(ctx, &arg))
+            .enumerate()
+            .map(|(idx, arg)| self.handle_arg(ctx, infos.operands().kind(idx), arg))
             .collect();
 
-        let ins = ctx.make_ins(def_name, opname, is_def, &vargs[..]);
+        let ins = ctx.make_ins(def_name, opcode, is_def, &vargs[..]);
+        let ins_obj = ins.own_mut(ctx).unwrap();
+        ins_obj.set_loc(self.loc.clone());
+        *ins_obj.comments_mut() = comments;
         ctx.ins_insert_in(ins, self.act_bb.unwrap());
         self.ins_list.push((ins, args.to_vec()));
 
@@ -121,20 +329,30 @@ impl CodeBuilder {
             self.vars_map.insert(def_name.to_string(), ins.into());
         }
 
-        if infos.is_term(args) {
+        if infos.is_term() {
             self.act_bb = None
         }
     }
 
-    fn handle_arg(&mut self, ctx: &mut Context, arg: &str) -> ValueRef {
+    fn handle_arg(&mut self, ctx: &mut Context, kind: Option<OperandKind>, arg: &str) -> ValueRef {
         let f = arg.chars().next().unwrap();
         if f == '@' || f == '%' {
-            //Register / Label, resolve later
-            return self.mock_var.unwrap();
+            //Register / Label already defined, or resolve later
+            let known = match f {
+                '%' => self.vars_map.get(&arg[1..]).copied(),
+                _ if kind == Some(OperandKind::Fun) => {
+                    self.funs_map.get(&arg[1..]).map(|f| (*f).into())
+                }
+                _ if kind == Some(OperandKind::Value) => Some(self.find_global(&arg[1..]).into()),
+                _ => self.bbs_map.get(&arg[1..]).map(|bb| (*bb).into()),
+            };
+            return known.unwrap_or_else(|| self.mock_var.unwrap());
         }
 
-        if f == '-' || (f >= '0' && f <= '9') {
-            let v: i64 = arg.parse().expect("invalid number argument");
+        if f == '-' || f.is_ascii_digit() {
+            let v: i64 = arg
+                .parse()
+                .unwrap_or_else(|_| self.error(format!("invalid number argument {}", arg)));
             return ctx.make_const("", v).into();
         }
 
@@ -142,22 +360,31 @@ impl CodeBuilder {
     }
 
     fn finish_fun(&mut self, ctx: &mut Context) {
-        let fun = self.act_fun.unwrap();
+        assert!(self.act_fun.is_some());
         if self.act_bb.is_some() {
-            panic!("function must finish with a term instruction");
+            self.loc = self.act_fun.unwrap().own(ctx).unwrap().loc().cloned();
+            self.error("function must finish with a term instruction".to_string());
         }
 
         let mut ins_list = vec![];
         std::mem::swap(&mut ins_list, &mut self.ins_list);
-        for ins in ins_list {
+        for ins in &ins_list {
+            self.loc = ins.0.own(ctx).unwrap().loc().cloned();
             self.resolve_ins(ctx, ins.0, &ins.1);
         }
+
+        // Types of phis and calls depend on the resolved operands
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for ins in &ins_list {
+                changed |= ctx.ins_update_type(ins.0);
+            }
+        }
     }
 
     fn resolve_ins(&mut self, ctx: &mut Context, ins: InstructionRef, args: &[String]) {
-        let opname = &args[0];
-        let infos = ISA::instance().find_ins(opname).unwrap();
-        let is_def = infos.is_def(args);
+        let is_def = ins.own(ctx).unwrap().val().is_def();
         let mock_var = self.mock_var.unwrap();
         let args_start = if is_def { 1 } else { 0 };
         let args_count = args.len() - 1 - args_start;
@@ -167,52 +394,112 @@ impl CodeBuilder {
             if old_val != mock_var {
                 continue;
             }
-            let new_val = self.resolve_arg(ctx, args, i + args_start + 1);
+            let kind = ins.own(ctx).unwrap().infos().operands().kind(i);
+            let new_val = self.resolve_arg(ctx, kind, args, i + args_start + 1);
             ctx.ins_set_op(ins, i, new_val);
         }
     }
 
-    fn resolve_arg(&mut self, ctx: &mut Context, args: &[String], idx: usize) -> ValueRef {
-        let opname = &args[0];
+    fn resolve_arg(
+        &mut self,
+        ctx: &mut Context,
+        kind: Option<OperandKind>,
+        args: &[String],
+        idx: usize,
+    ) -> ValueRef {
         let arg = &args[idx];
         let f = arg.chars().next().unwrap();
 
         if f == '%' {
-            *self.vars_map.get(&arg[1..]).expect(&format!(
-                "Use undefined register value {} at {:?}",
-                &arg[1..],
-                args
-            ))
+            *self.vars_map.get(&arg[1..]).unwrap_or_else(|| {
+                self.error(format!("Use undefined register value {}", &arg[1..]))
+            })
         } else if f == '@' {
-            let is_fun = opname == "call";
-            if is_fun {
+            if kind == Some(OperandKind::Fun) {
                 self.find_fun(ctx, &arg[1..]).into()
             } else {
-                (*self
-                    .bbs_map
-                    .get(&arg[1..])
-                    .expect("Use undefined basic block"))
+                (*self.bbs_map.get(&arg[1..]).unwrap_or_else(|| {
+                    self.error(format!("Use undefined basic block {}", &arg[1..]))
+                }))
                 .into()
             }
         } else {
-            panic!("bad arg: {:?} - {:?}", args, arg)
+            self.error(format!("bad arg {} of {}", arg, args[0]))
         }
     }
 
-    // Find or insert a function name
-    // @TODO: doesn't handle case where referencing a function defined later
+    fn find_global(&self, name: &str) -> GlobalRef {
+        *self
+            .globals_map
+            .get(name)
+            .unwrap_or_else(|| self.error(format!("Use undefined global {}", name)))
+    }
+
+    // Panics with the location of the current declaration
+    fn error(&self, msg: String) -> ! {
+        panic!("{}{}", loc_prefix(self.loc.as_ref()), msg)
+    }
+
+    // Find a function name, builtins are declared on first use
     fn find_fun(&mut self, ctx: &mut Context, name: &str) -> FunctionRef {
-        let it = self.funs_map.get(name);
-        if it.is_some() {
-            return *it.unwrap();
+        if let Some(fun) = self.funs_map.get(name) {
+            return *fun;
         }
 
-        let fun = ctx.make_fun(name, 0, true);
+        let (_, ret_ty, args_tys, attrs) = BUILTINS
+            .iter()
+            .find(|builtin| builtin.0 == name)
+            .unwrap_or_else(|| self.error(format!("Use undeclared function {}", name)));
+        let fun = declare_fun(ctx, name, *ret_ty, args_tys);
+        for attr in *attrs {
+            fun.own_mut(ctx).unwrap().add_attr(*attr);
+        }
         self.funs_map.insert(name.to_string(), fun);
         fun
     }
 }
 
+// Runtime functions, declared on first use
+// (name, return type, argument types, attributes)
+type Builtin = (&'static str, Type, &'static [Type], &'static [Attr]);
+const BUILTINS: &[Builtin] = &[
+    ("_std_print", Type::Void, &[Type::I64], &[Attr::NoUnwind]),
+    (
+        "_std_exit",
+        Type::Void,
+        &[Type::I64],
+        &[Attr::NoReturn, Attr::NoUnwind],
+    ),
+];
+
+fn declare_fun(ctx: &mut Context, name: &str, ret_ty: Type, args_tys: &[Type]) -> FunctionRef {
+    let fun = ctx.make_fun(name, args_tys.len(), true);
+    fun.own_mut(ctx).unwrap().set_ret_ty(ret_ty);
+    for (idx, ty) in args_tys.iter().enumerate() {
+        ctx.fun_get_arg_mut(fun, idx).set_ty(*ty);
+    }
+    fun
+}
+
+// Name of the function of `.decl name:ret, ...`
+fn decl_name(d: &gop::Dir) -> &str {
+    let arg = &d.args()[1];
+    arg.split_once(':').map_or(arg, |(name, _)| name)
+}
+
+// Calls define a value when their first argument is a register
+fn is_def(infos: &InsInfos, args: &[String]) -> bool {
+    match infos.def() {
+        DefKind::Never => false,
+        DefKind::Always => true,
+        DefKind::Optional => args.len() > 1 && args[1].starts_with('%'),
+    }
+}
+
+fn decl_comments(decl: &gop::Decl) -> Comments {
+    Comments::new(decl.comm_pre(), decl.comm_eol())
+}
+
 pub fn load_gop(ctx: &mut Context, gmod: &gop::Module) {
     CodeBuilder::new().run(ctx, gmod);
 }
@@ -220,60 +507,134 @@ pub fn load_gop(ctx: &mut Context, gmod: &gop::Module) {
 pub fn build_gop(ctx: &Context) -> gop::Module {
     let mut decls: Vec<gop::Decl> = vec![];
 
+    for global in ctx.globals() {
+        let global = global.own(ctx).unwrap();
+        let kind = if global.is_const() { "const" } else { "global" };
+        let ty = match global.len() {
+            1 => "i64".to_string(),
+            len => format!("i64[{}]", len),
+        };
+        let mut dir_args = vec![kind.to_string(), ty];
+        dir_args.extend(global.init().iter().map(|v| v.to_string()));
+
+        let comments = global.comments();
+        decls.push(gop::Decl::new_dir(
+            vec![global.val().name().to_string()],
+            comments.pre().to_vec(),
+            comments.eol().to_string(),
+            dir_args,
+        ));
+    }
+
     for fun in ctx.funs() {
         let fun = fun.own(ctx).unwrap();
-        if fun.is_decl() {
+        if !fun.is_decl() {
             continue;
         }
 
+        let mut dir_args = vec!["decl".to_string()];
+        dir_args.push(match fun.ret_ty() {
+            Type::I64 => fun.val().name().to_string(),
+            ty => format!("{}:{}", fun.val().name(), ty),
+        });
+        dir_args.extend(
+            fun.args()
+                .iter()
+                .map(|arg| arg.own(ctx).unwrap().ty().to_string()),
+        );
+        let comments = fun.comments();
+        decls.push(gop::Decl::new_dir(
+            vec![],
+            comments.pre().to_vec(),
+            comments.eol().to_string(),
+            dir_args,
+        ));
+        push_attrs(&mut decls, fun);
+    }
+
+    let mut builder = GopBuilder {
+        decls,
+        bb_label: None,
+    };
+    visitor::walk(ctx, &mut builder);
+    gop::Module::new(builder.decls)
+}
+
+// Declarations of the defined functions
+struct GopBuilder {
+    decls: Vec<gop::Decl>,
+
+    // Label and comments of the block, given to its first instruction
+    bb_label: Option<(String, Vec<String>)>,
+}
+
+impl Visitor for GopBuilder {
+    fn visit_fun(&mut self, ctx: &Context, fun: &Function) {
         let fun_name = fun.val().name().to_string();
         let mut args_names = fun
             .args()
             .iter()
-            .map(|arg| "%".to_string() + arg.own(ctx).unwrap().val().name())
+            .map(|arg| {
+                let arg = arg.own(ctx).unwrap();
+                match arg.ty() {
+                    Type::I64 => format!("%{}", arg.val().name()),
+                    ty => format!("%{}:{}", arg.val().name(), ty),
+                }
+            })
             .collect::<Vec<_>>();
-        let mut dir_args = vec!["fun".to_string(), "int".to_string()];
+        let mut dir_args = vec!["fun".to_string(), fun.ret_ty().name().to_string()];
         dir_args.append(&mut args_names);
 
-        decls.push(gop::Decl::new_dir(
+        let comments = fun.comments();
+        self.decls.push(gop::Decl::new_dir(
             vec![fun_name],
-            vec![],
-            String::new(),
+            comments.pre().to_vec(),
+            comments.eol().to_string(),
             dir_args,
         ));
+        push_attrs(&mut self.decls, fun);
+    }
 
-        for bb in fun.bbs() {
-            let bb = bb.own(ctx).unwrap();
-            let mut bb_label = Some(bb.val().name());
-
-            for ins in bb.ins() {
-                let ins = ins.own(ctx).unwrap();
-
-                let mut ins_args = vec![ins.opname().to_string()];
-                if ins.val().is_def() {
-                    ins_args.push("%".to_string() + ins.val().name());
-                }
-                ins_args.append(
-                    &mut ins
-                        .val()
-                        .ops()
-                        .iter()
-                        .map(|arg| val_to_gop_arg(ctx, *arg))
-                        .collect::<Vec<String>>(),
-                );
-
-                let labels = match bb_label {
-                    Some(l) => vec![l.to_string()],
-                    None => vec![],
-                };
-                bb_label = None;
+    // Comments of the block come first, as one-line comments
+    fn visit_bb(&mut self, _ctx: &Context, bb: &BasicBlock) {
+        let comments = bb.comments();
+        let mut comm_pre = comments.pre().to_vec();
+        if !comments.eol().is_empty() {
+            comm_pre.push(comments.eol().to_string());
+        }
+        self.bb_label = Some((bb.val().name().to_string(), comm_pre));
+    }
 
-                decls.push(gop::Decl::new_ins(labels, vec![], String::new(), ins_args));
-            }
+    fn visit_ins(&mut self, ctx: &Context, ins: &Instruction) {
+        let mut ins_args = vec![ins.opname().to_string()];
+        if ins.val().is_def() {
+            ins_args.push("%".to_string() + ins.val().name());
         }
+        ins_args.extend(ins.val().ops().iter().map(|arg| val_to_gop_arg(ctx, *arg)));
+
+        let (labels, mut comm_pre) = match self.bb_label.take() {
+            Some((label, comm_pre)) => (vec![label], comm_pre),
+            None => (vec![], vec![]),
+        };
+        comm_pre.extend_from_slice(ins.comments().pre());
+
+        self.decls.push(gop::Decl::new_ins(
+            labels,
+            comm_pre,
+            ins.comments().eol().to_string(),
+            ins_args,
+        ));
     }
+}
 
-    gop::Module::new(decls)
+// `.attr` directive after `.fun` or `.decl`, if any attribute
+fn push_attrs(decls: &mut Vec<gop::Decl>, fun: &Function) {
+    if fun.attrs().is_empty() {
+        return;
+    }
+    let mut dir_args = vec!["attr".to_string()];
+    dir_args.extend(fun.attrs().iter().map(|attr| attr.name().to_string()));
+    decls.push(gop::Decl::new_dir(vec![], vec![], String::new(), dir_args));
 }
 
 fn val_to_gop_arg(ctx: &Context, val: ValueRef) -> String {
@@ -283,22 +644,15 @@ fn val_to_gop_arg(ctx: &Context, val: ValueRef) -> String {
         ValueRefEnum::Fun(r) => "@".to_string() + r.own(ctx).unwrap().val().name(),
         ValueRefEnum::Const(r) => r.own(ctx).unwrap().const_int().to_string(),
         ValueRefEnum::Arg(r) => "%".to_string() + r.own(ctx).unwrap().val().name(),
-        _ => unreachable!(),
+        ValueRefEnum::Global(r) => "@".to_string() + r.own(ctx).unwrap().val().name(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    fn find_path(path: &str) -> String {
-        use std::path::Path;
-        Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join(path)
-            .to_str()
-            .unwrap()
-            .to_string()
-    }
+    use crate::checker;
+    use crate::test_utils::find_path;
 
     fn test_file(path: &str) {
         let path = find_path(path);
@@ -324,4 +678,231 @@ mod tests {
     fn load_cycle1() {
         test_file("examples/cycle1.ir");
     }
+
+    #[test]
+    fn load_index() {
+        test_file("examples/index.ir");
+    }
+
+    #[test]
+    fn load_arith() {
+        let path = find_path("examples/arith.ir");
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gop::Module::parse(&path));
+        checker::check_code(&ctx);
+
+        let out = format!("{}", build_gop(&ctx));
+        for ins in &[
+            "rem %r, %x, %y",
+            "cmpne %nz, %r, 0",
+            "select %v2, %over, %hi, %v1",
+            "or %out, %under, %over",
+            "div %h, %s, 2",
+            "neg %nm, %m",
+            "cmpuge %nsmall, %lo, %hi",
+        ] {
+            assert!(out.contains(ins), "missing {}", ins);
+        }
+    }
+
+    #[test]
+    fn load_globals() {
+        let path = find_path("examples/globals.ir");
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gop::Module::parse(&path));
+        checker::check_code(&ctx);
+
+        let primes = ctx.globals().nth(1).unwrap();
+        let primes = primes.own(&ctx).unwrap();
+        assert!(primes.is_const());
+        assert_eq!(primes.len(), 8);
+        assert_eq!(primes.init(), &[2, 3, 5, 7, 11, 13]);
+
+        let out = format!("{}", build_gop(&ctx));
+        assert!(out.contains("counter:\n.global i64, 0\n"));
+        assert!(out.contains("primes:\n.const i64[8], 2, 3, 5, 7, 11, 13\n"));
+        assert!(out.contains("offset %p, @primes, %off"));
+        assert!(out.contains("store %k1, @counter"));
+
+        // Loading the output again gives the same module
+        let gmod = gop::Module::parse_str("out.ir", &out);
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gmod);
+        assert_eq!(format!("{}", build_gop(&ctx)), out);
+    }
+
+    #[test]
+    fn load_comments() {
+        let path = find_path("examples/comments.ir");
+        let gmod = gop::Module::parse(&path);
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gmod);
+        checker::check_code(&ctx);
+
+        let fun = ctx.funs().nth(2).unwrap();
+        let fun = fun.own(&ctx).unwrap();
+        assert_eq!(fun.comments().pre(), &["Sum of the first n squares"]);
+        assert_eq!(fun.comments().eol(), "n >= 0");
+        assert_eq!(format!("{}", build_gop(&ctx)), format!("{}", gmod));
+    }
+
+    #[test]
+    fn load_attrs() {
+        let path = find_path("examples/attrs.ir");
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gop::Module::parse(&path));
+        checker::check_code(&ctx);
+
+        let funs: Vec<_> = ctx.funs().collect();
+        let hash = funs[0].own(&ctx).unwrap();
+        assert!(hash.is_decl());
+        assert!(hash.attrs().has(Attr::Pure));
+        let exit = funs
+            .iter()
+            .map(|fun| fun.own(&ctx).unwrap())
+            .find(|fun| fun.val().name() == "_std_exit")
+            .unwrap();
+        assert_eq!(exit.args().len(), 1);
+        assert!(exit.attrs().is_noreturn());
+
+        let out = format!("{}", build_gop(&ctx));
+        assert!(out.contains(".decl hash, i64\n.attr pure\n"));
+        assert!(out.contains(".decl _std_exit:void, i64\n.attr noreturn, nounwind\n"));
+
+        // Loading the output again gives the same module
+        let gmod = gop::Module::parse_str("out.ir", &out);
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gmod);
+        assert_eq!(format!("{}", build_gop(&ctx)), out);
+    }
+
+    #[test]
+    #[should_panic(expected = ".ir:5:2: Use undeclared function nowhere")]
+    fn load_undeclared_fun() {
+        let gmod = gop::Module::parse_str(
+            "undecl.ir",
+            "f:\n.fun void\n\nB0:\n\tcall @nowhere\n\tret\n",
+        );
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gmod);
+    }
+
+    #[test]
+    fn load_forward_call() {
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "forward.ir",
+            "f:\n.fun i64, %x\n\nB0:\n\tcall %y, @g, %x\n\tret %y\n\ng:\n.fun i64, %x\n\nB0:\n\tret %x\n",
+        );
+        load_gop(&mut ctx, &gmod);
+        checker::check_code(&ctx);
+        assert_eq!(format!("{}", build_gop(&ctx)), format!("{}", gmod));
+    }
+
+    #[test]
+    fn load_decl_then_fun() {
+        // The definition takes the place of the declaration, with its attributes
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "decl_fun.ir",
+            ".decl g, i64\n.attr pure\n\nf:\n.fun i64, %x\n\nB0:\n\tcall %y, @g, %x\n\tret %y\n\ng:\n.fun i64, %x\n\nB0:\n\tret %x\n",
+        );
+        load_gop(&mut ctx, &gmod);
+        checker::check_code(&ctx);
+
+        let funs: Vec<_> = ctx.funs().collect();
+        assert_eq!(funs.len(), 2);
+        let g = funs[0].own(&ctx).unwrap();
+        assert!(!g.is_decl());
+        assert!(g.attrs().has(Attr::Pure));
+        let out = format!("{}", build_gop(&ctx));
+        assert!(!out.contains(".decl"));
+        assert!(out.contains("g:\n.fun i64, %x\n.attr pure\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "decl_fun.ir:4:1: Function g redeclared with a different signature")]
+    fn load_decl_mismatch() {
+        let gmod = gop::Module::parse_str(
+            "decl_fun.ir",
+            ".decl g, i64\n\ng:\n.fun void, %x\n\nB0:\n\tret\n",
+        );
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gmod);
+    }
+
+    #[test]
+    fn load_locs() {
+        let path = find_path("examples/fact_iter.ir");
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gop::Module::parse(&path));
+
+        let fact = ctx.funs().next().unwrap();
+        let bb = fact.own(&ctx).unwrap().bbs()[1];
+        let bb = bb.own(&ctx).unwrap();
+        // Blocks are located at their first instruction
+        assert_eq!(bb.loc().unwrap().line(), 9);
+        let mul = bb.ins()[2].own(&ctx).unwrap();
+        assert_eq!(mul.opcode(), Opcode::Mul);
+        assert_eq!(mul.loc().unwrap().to_string(), format!("{}:11:2", path));
+    }
+
+    #[test]
+    #[should_panic(expected = ".ir:5:2: Use undefined basic block nowhere")]
+    fn load_undefined_bb() {
+        let gmod = gop::Module::parse_str("undef.ir", "f:\n.fun void\n\nB0:\n\tb @nowhere\n");
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gmod);
+    }
+
+    #[test]
+    fn load_types() {
+        let path = find_path("examples/entries.ir");
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gop::Module::parse(&path));
+        checker::check_code(&ctx);
+
+        let funs: Vec<_> = ctx.funs().collect();
+        let count = funs[0].own(&ctx).unwrap();
+        assert_eq!(count.ret_ty(), Type::I64);
+        assert_eq!(count.args()[1].own(&ctx).unwrap().ty(), Type::I1);
+        assert_eq!(funs[1].own(&ctx).unwrap().ret_ty(), Type::Void);
+
+        let out = format!("{}", build_gop(&ctx));
+        assert!(out.contains(".fun i64, %n, %c:i1"));
+        assert!(out.contains(".fun void"));
+    }
+
+    #[test]
+    fn load_large() {
+        // Every literal is a new constant: more than 65536 of each kind
+        const COUNT: usize = 100_000;
+        let mut code = String::from("big:\n.fun int, %x\n\nB0:\n\tadd %v0, %x, 1\n");
+        for i in 1..COUNT {
+            code.push_str(&format!("\tadd %v{}, %v{}, {}\n", i, i - 1, i));
+        }
+        code.push_str(&format!("\tret %v{}\n", COUNT - 1));
+
+        let gmod = gop::Module::parse_str("large.ir", &code);
+
+        let mut ctx = Context::new();
+        load_gop(&mut ctx, &gmod);
+        checker::check_code(&ctx);
+
+        let fun = ctx.funs().next().unwrap();
+        let bb = fun.own(&ctx).unwrap().bbs()[0];
+        let ins = bb.own(&ctx).unwrap().ins();
+        assert_eq!(ins.len(), COUNT + 1);
+
+        let last = ins[COUNT - 1].own(&ctx).unwrap();
+        assert_eq!(last.val().name(), format!("v{}", COUNT - 1));
+        let ops = last.val().ops();
+        assert_eq!(ops[0], ins[COUNT - 2].into());
+        match ops[1].to_enum() {
+            ValueRefEnum::Const(c) => {
+                assert_eq!(c.own(&ctx).unwrap().const_int(), (COUNT - 1) as i64)
+            }
+            _ => panic!("expected a constant"),
+        }
+    }
 }
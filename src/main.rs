@@ -1,21 +1,42 @@
+#![allow(clippy::upper_case_acronyms)]
+
+mod arena;
 mod argument;
+mod attrs;
 mod basicblock;
 mod cfg;
+mod cfg_utils;
 mod checker;
+mod comments;
 mod constant;
 mod context;
+mod dce;
+mod debug_loc;
 mod digraph;
 mod digraph_order;
 mod dom_tree;
 mod function;
+mod global;
 mod gop;
 mod indexable;
 mod instruction;
+mod ir_builder;
 mod isa;
+mod iv_analysis;
 mod loader;
+mod names;
+mod osr;
+mod peephole;
+mod phi;
+mod remarks;
+mod ssa_graph;
+#[cfg(test)]
+mod test_utils;
+mod types;
 mod value;
 mod valueref;
 mod vertex_adapter;
+mod visitor;
 
 #[macro_use]
 extern crate lazy_static;
@@ -23,17 +44,60 @@ extern crate lazy_static;
 use crate::cfg::CFG;
 use crate::context::Context;
 use crate::dom_tree::DomTree;
+use crate::iv_analysis::IVAnalysis;
+use crate::remarks::Remarks;
+use crate::ssa_graph::SSAGraph;
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let fpath = args.get(1).expect("Missing file path");
+    let mut fpath = None;
+    let mut remarks_format = None;
+    let mut dump_ivs = false;
+    for arg in std::env::args().skip(1) {
+        match &arg[..] {
+            "--remarks" | "--remarks=text" => remarks_format = Some("text"),
+            "--remarks=json" => remarks_format = Some("json"),
+            "--dump-ivs" => dump_ivs = true,
+            _ => fpath = Some(arg),
+        }
+    }
+    let fpath = fpath.expect("Missing file path");
+
     let mut ctx = Context::new();
     loader::load_gop(&mut ctx, &gop::Module::parse(&fpath));
     checker::check_code(&ctx);
 
+    // Induction variables of the input, before any transformation
+    if dump_ivs {
+        let mut err = std::io::stderr();
+        for fun in ctx.funs() {
+            if !fun.own(&ctx).unwrap().is_decl() {
+                IVAnalysis::new(&ctx, fun)
+                    .dump(&ctx, &mut err)
+                    .expect("Failed to write induction variables");
+            }
+        }
+    }
+
+    let mut remarks = Remarks::new();
+    osr::run(&mut ctx, &mut remarks);
+    peephole::run(&mut ctx, &mut remarks);
+    dce::run(&mut ctx, &mut remarks);
+    checker::check_code(&ctx);
+
     let gmod = loader::build_gop(&ctx);
     println!("{}", gmod);
 
+    let mut err = std::io::stderr();
+    match remarks_format {
+        Some("text") => remarks
+            .dump_text(&mut err)
+            .expect("Failed to write remarks"),
+        Some("json") => remarks
+            .dump_json(&mut err)
+            .expect("Failed to write remarks"),
+        _ => {}
+    }
+
     for fun in ctx.funs() {
         let fun = fun.own(&ctx).unwrap();
         if fun.is_decl() {
@@ -45,5 +109,8 @@ fn main() {
 
         let dom = DomTree::new(&ctx, &cfg, fun.id());
         dom.save_tree(&format!("./dom_{}.dot", fun.val().name()));
+
+        let ssa = SSAGraph::new(&ctx, fun.id());
+        ssa.save_tree(&format!("./ssa_{}.dot", fun.val().name()));
     }
 }
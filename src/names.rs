@@ -0,0 +1,102 @@
+// Names of new values and blocks
+//
+// Every pass creating values or blocks takes their names from a
+// `NameAllocator` built from the names already in the function, so that
+// generated names never collide with existing ones or with each other.
+// Values (arguments and instructions) and blocks have separate names.
+
+use crate::context::Context;
+use crate::valueref::FunctionRef;
+
+use std::collections::HashSet;
+
+#[derive(Default)]
+pub struct NameAllocator {
+    taken: HashSet<String>,
+    next: usize,
+}
+
+impl NameAllocator {
+    pub fn new() -> NameAllocator {
+        NameAllocator::default()
+    }
+
+    // Names of the arguments and instructions of `fun`
+    pub fn of_values(ctx: &Context, fun: FunctionRef) -> NameAllocator {
+        let mut res = NameAllocator::new();
+        res.add_values(ctx, fun);
+        res
+    }
+
+    // Names of the blocks of `fun`
+    pub fn of_blocks(ctx: &Context, fun: FunctionRef) -> NameAllocator {
+        let mut res = NameAllocator::new();
+        for bb in fun.own(ctx).unwrap().bbs() {
+            res.add(bb.own(ctx).unwrap().val().name());
+        }
+        res
+    }
+
+    pub fn add_values(&mut self, ctx: &Context, fun: FunctionRef) {
+        let fun = fun.own(ctx).unwrap();
+        for arg in fun.args() {
+            self.add(arg.own(ctx).unwrap().val().name());
+        }
+        for bb in fun.bbs() {
+            for ins in bb.own(ctx).unwrap().ins() {
+                self.add(ins.own(ctx).unwrap().val().name());
+            }
+        }
+    }
+
+    pub fn add(&mut self, name: &str) {
+        self.taken.insert(name.to_string());
+    }
+
+    // `name` can be given again, e.g. once its value is renamed
+    pub fn remove(&mut self, name: &str) {
+        self.taken.remove(name);
+    }
+
+    // `base`, or `base.N` if already taken
+    pub fn fresh(&mut self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut idx = 1;
+        while self.taken.contains(&name) {
+            name = format!("{}.{}", base, idx);
+            idx += 1;
+        }
+        self.add(&name);
+        name
+    }
+
+    // `prefix` followed by the next number not taken, e.g. `v0`, `v1`...
+    pub fn numbered(&mut self, prefix: &str) -> String {
+        loop {
+            let name = format!("{}{}", prefix, self.next);
+            self.next += 1;
+            if !self.taken.contains(&name) {
+                self.add(&name);
+                return name;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_fresh() {
+        let mut names = NameAllocator::new();
+        names.add("i");
+        names.add("v1");
+        assert_eq!(names.fresh("i"), "i.1");
+        assert_eq!(names.fresh("i"), "i.2");
+        assert_eq!(names.fresh("j"), "j");
+        assert_eq!(names.numbered("v"), "v0");
+        assert_eq!(names.numbered("v"), "v2");
+        assert_eq!(names.fresh("v2"), "v2.1");
+    }
+}
@@ -0,0 +1,1153 @@
+// Operator Strength Reduction
+// Keith D. Cooper, L. Taylor Simpson, Christopher A. Vick
+// Engineering a Compiler, second edition, section 10.7.2
+//
+// SCCs of the SSA graph are visited operands before users. Each member of
+// an SCC that is not an induction variable and computes `iv * rc`,
+// `iv + rc`, `iv - rc` or `iv << c` is replaced by a new induction variable
+// built by cloning the IV cycle.
+//
+// Linear Function Test Replacement then rewrites comparisons against an
+// original induction variable to use its reduced version, when the original
+// is then left without users, and removes the induction variables left
+// without users outside their own cycle.
+
+use crate::cfg_utils;
+use crate::context::Context;
+use crate::debug_loc::DebugLoc;
+use crate::ir_builder::{InsertPoint, IrBuilder};
+use crate::isa::Opcode;
+use crate::iv_analysis::IVAnalysis;
+use crate::names::NameAllocator;
+use crate::remarks::Remarks;
+use crate::valueref::{BasicBlockRef, FunctionRef, InstructionRef, ValueRef, ValueRefEnum};
+
+use std::collections::{HashMap, HashSet};
+
+const PASS_NAME: &str = "osr";
+
+// Operand of a memoized computation
+// Constants are compared by value, as each literal gets its own constant
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum TableOperand {
+    Val(ValueRef),
+    Const(i64),
+}
+
+impl TableOperand {
+    fn new(ctx: &Context, v: ValueRef) -> TableOperand {
+        match const_val(ctx, v) {
+            Some(c) => TableOperand::Const(c),
+            None => TableOperand::Val(v),
+        }
+    }
+}
+
+// Values created by the pass, keyed by (opcode, o1, o2)
+// Reductions are keyed with the IV member as first operand, so every
+// `iv op rc` of the function shares the same new induction variable
+struct ReductionTable {
+    map: HashMap<(Opcode, TableOperand, TableOperand), ValueRef>,
+}
+
+impl ReductionTable {
+    fn new() -> ReductionTable {
+        ReductionTable {
+            map: HashMap::new(),
+        }
+    }
+
+    fn get(&self, ctx: &Context, op: Opcode, o1: ValueRef, o2: ValueRef) -> Option<ValueRef> {
+        let (o1, o2) = (TableOperand::new(ctx, o1), TableOperand::new(ctx, o2));
+        let res = self.map.get(&(op, o1, o2));
+        if res.is_none() && op.infos().is_commutative() {
+            self.map.get(&(op, o2, o1)).copied()
+        } else {
+            res.copied()
+        }
+    }
+
+    fn insert(&mut self, ctx: &Context, op: Opcode, o1: ValueRef, o2: ValueRef, res: ValueRef) {
+        let (o1, o2) = (TableOperand::new(ctx, o1), TableOperand::new(ctx, o2));
+        self.map.insert((op, o1, o2), res);
+    }
+}
+
+// Induction variable `header = phi(pre: init, latch: header +/- step)`
+struct SimpleIV {
+    header: InstructionRef,
+    pre: BasicBlockRef,
+    init: ValueRef,
+    latch: BasicBlockRef,
+    update: InstructionRef,
+}
+
+struct OSR<'a> {
+    fun: FunctionRef,
+    fun_name: String,
+    remarks: &'a mut Remarks,
+    ivs: IVAnalysis,
+    table: ReductionTable,
+
+    // LFTR edges: reduced versions of an IV member, as (op, rc, new member)
+    edges: HashMap<InstructionRef, Vec<(Opcode, ValueRef, InstructionRef)>>,
+
+    // New IVs built by `reduce_iv`, one group of clones per IV
+    reduced: Vec<Vec<InstructionRef>>,
+
+    names: NameAllocator,
+
+    // Location given to new instructions, the one of the instruction being reduced
+    loc: Option<DebugLoc>,
+}
+
+impl<'a> OSR<'a> {
+    fn new(ctx: &Context, fun: FunctionRef, remarks: &'a mut Remarks) -> OSR<'a> {
+        OSR {
+            fun,
+            fun_name: fun.own(ctx).unwrap().val().name().to_string(),
+            remarks,
+            ivs: IVAnalysis::new(ctx, fun),
+            table: ReductionTable::new(),
+            edges: HashMap::new(),
+            reduced: vec![],
+            names: NameAllocator::of_values(ctx, fun),
+            loc: None,
+        }
+    }
+
+    fn run(&mut self, ctx: &mut Context) {
+        let sccs = self.ivs.sccs().to_vec();
+        for scc in sccs {
+            if !self.ivs.is_iv(scc[0].into()) {
+                self.process(ctx, &scc);
+            }
+        }
+
+        self.lftr(ctx);
+        self.remove_dead_ivs(ctx);
+    }
+
+    fn process(&mut self, ctx: &mut Context, scc: &[InstructionRef]) {
+        for n in scc {
+            self.loc = n.own(ctx).unwrap().loc().cloned();
+            if let Some((iv, rc)) = self.candidate(ctx, *n) {
+                self.replace(ctx, *n, iv, rc);
+            } else if let Some((x, y)) = self.poly_candidate(ctx, *n) {
+                self.replace_poly(ctx, *n, x, y);
+            } else if matches!(n.own(ctx).unwrap().opcode(), Opcode::Mul | Opcode::Shl) {
+                let reason = self.skip_reason(ctx, *n);
+                self.remarks.skipped(
+                    PASS_NAME,
+                    &self.fun_name,
+                    &ins_name(ctx, *n),
+                    n.own(ctx).unwrap().loc(),
+                    reason,
+                );
+            }
+        }
+    }
+
+    // Explain why `n` is not a candidate
+    fn skip_reason(&self, ctx: &Context, n: InstructionRef) -> String {
+        let n = n.own(ctx).unwrap();
+        let ops = n.val().ops();
+        if n.opcode() == Opcode::Shl && shift_amount(ctx, ops[1]).is_none() {
+            let amount = val_name(ctx, ops[1]);
+            return match const_val(ctx, ops[1]) {
+                Some(_) => format!("shift amount {} is out of range", amount),
+                None => format!("shift amount {} is not a constant", amount),
+            };
+        }
+
+        let ivs: Vec<bool> = ops.iter().map(|op| self.ivs.is_iv(*op)).collect();
+
+        if ivs.iter().all(|x| *x) {
+            return "operands are not simple induction variables of the same loop".to_string();
+        }
+
+        for op in ops {
+            if let Some(reason) = self.ivs.reject_reason(*op) {
+                return format!("{} ({})", reason, val_name(ctx, *op));
+            }
+        }
+
+        for (iv_pos, rc_pos) in &[(0, 1), (1, 0)] {
+            if ivs[*iv_pos] {
+                return format!(
+                    "operand {} not a region constant",
+                    val_name(ctx, ops[*rc_pos])
+                );
+            }
+        }
+
+        "no operand is an induction variable".to_string()
+    }
+
+    // Returns (iv, rc) if `n` computes `iv * rc`, `iv + rc`, `iv - rc`
+    // or `iv << c`
+    fn candidate(&self, ctx: &Context, n: InstructionRef) -> Option<(InstructionRef, ValueRef)> {
+        let n = n.own(ctx).unwrap();
+        let ops = n.val().ops();
+        let orders: &[(usize, usize)] = match n.opcode() {
+            Opcode::Mul | Opcode::Add => &[(0, 1), (1, 0)],
+            Opcode::Sub => &[(0, 1)],
+            Opcode::Shl if shift_amount(ctx, ops[1]).is_some() => &[(0, 1)],
+            _ => return None,
+        };
+
+        for (iv_pos, rc_pos) in orders {
+            let (iv, rc) = (ops[*iv_pos], ops[*rc_pos]);
+            let header = match self.iv_header(iv) {
+                Some(header) => header,
+                None => continue,
+            };
+            if self.is_region_const(ctx, rc, header) {
+                return Some((iv.raw().into(), rc));
+            }
+        }
+
+        None
+    }
+
+    fn replace(&mut self, ctx: &mut Context, n: InstructionRef, iv: InstructionRef, rc: ValueRef) {
+        let op = n.own(ctx).unwrap().opcode();
+
+        // `iv << c` is reduced as `iv * 2^c`, sharing the IVs of the muls
+        let (op, rc) = match shift_amount(ctx, rc) {
+            Some(c) if op == Opcode::Shl => (Opcode::Mul, ctx.make_const("", 1 << c).into()),
+            _ => (op, rc),
+        };
+        let res = self.reduce_iv(ctx, op, iv, rc);
+        self.remarks.applied(
+            PASS_NAME,
+            &self.fun_name,
+            &ins_name(ctx, n),
+            n.own(ctx).unwrap().loc(),
+            format!("reduced into new IV {}", val_name(ctx, res.into())),
+        );
+        note_reduced_from(ctx, res, n);
+        ctx.replace_all_uses_with(n.into(), res.into());
+        ctx.erase_ins(n);
+    }
+
+    // Returns (x, y) if `n` computes `x * y`, with x and y simple IVs of the same loop
+    fn poly_candidate(&self, ctx: &Context, n: InstructionRef) -> Option<(ValueRef, ValueRef)> {
+        let n = n.own(ctx).unwrap();
+        if n.opcode() != Opcode::Mul {
+            return None;
+        }
+
+        let (x, y) = (n.val().ops()[0], n.val().ops()[1]);
+        if self.is_poly(ctx, x, y) {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    fn is_poly(&self, ctx: &Context, x: ValueRef, y: ValueRef) -> bool {
+        let (iv_x, iv_y) = match (self.simple_iv(ctx, x), self.simple_iv(ctx, y)) {
+            (Some(iv_x), Some(iv_y)) => (iv_x, iv_y),
+            _ => return false,
+        };
+        iv_x.header.own(ctx).unwrap().parent() == iv_y.header.own(ctx).unwrap().parent()
+            && iv_x.pre == iv_y.pre
+            && iv_x.latch == iv_y.latch
+    }
+
+    fn simple_iv(&self, ctx: &Context, v: ValueRef) -> Option<SimpleIV> {
+        let header = self.iv_header(v)?;
+        let ops = header.own(ctx).unwrap().val().ops();
+        if ops.len() != 4 {
+            return None;
+        }
+
+        let (pre, init, latch, update) = if self.iv_header(ops[3]) == Some(header) {
+            (ops[0], ops[1], ops[2], ops[3])
+        } else {
+            (ops[2], ops[3], ops[0], ops[1])
+        };
+        if self.iv_header(update) != Some(header) || (v != header.into() && v != update) {
+            return None;
+        }
+
+        let update: InstructionRef = update.raw().into();
+        let update_obj = update.own(ctx).unwrap();
+        let update_ops = update_obj.val().ops();
+        let is_step = match update_obj.opcode() {
+            Opcode::Add => update_ops.contains(&header.into()),
+            Opcode::Sub => update_ops[0] == header.into(),
+            _ => false,
+        };
+        if !is_step {
+            return None;
+        }
+
+        Some(SimpleIV {
+            header,
+            pre: pre.raw().into(),
+            init,
+            latch: latch.raw().into(),
+            update,
+        })
+    }
+
+    // Value added to a simple IV at each iteration
+    fn iv_step(&mut self, ctx: &mut Context, iv: &SimpleIV) -> ValueRef {
+        let update = iv.update.own(ctx).unwrap();
+        let ops = update.val().ops().to_vec();
+        if update.opcode() == Opcode::Sub {
+            let zero = ctx.make_const("", 0).into();
+            self.apply(ctx, Opcode::Sub, zero, ops[1], iv.pre)
+        } else if ops[0] == iv.header.into() {
+            ops[1]
+        } else {
+            ops[0]
+        }
+    }
+
+    fn replace_poly(&mut self, ctx: &mut Context, n: InstructionRef, x: ValueRef, y: ValueRef) {
+        let res = self.reduce_poly(ctx, x, y);
+        self.remarks.applied(
+            PASS_NAME,
+            &self.fun_name,
+            &ins_name(ctx, n),
+            n.own(ctx).unwrap().loc(),
+            format!(
+                "reduced into polynomial recurrence {}",
+                val_name(ctx, res.into())
+            ),
+        );
+        note_reduced_from(ctx, res, n);
+        ctx.replace_all_uses_with(n.into(), res.into());
+        ctx.erase_ins(n);
+    }
+
+    // Compute `x * y` with finite differences
+    // With x(k+1) = x(k) + a and y(k+1) = y(k) + b:
+    //   q(k+1) = q(k) + d(k), with q(0) = x(0) * y(0)
+    //   d(k+1) = d(k) + 2ab,  with d(0) = a * y(0) + b * x(0) + ab
+    fn reduce_poly(&mut self, ctx: &mut Context, x: ValueRef, y: ValueRef) -> InstructionRef {
+        if let Some(res) = self.table.get(ctx, Opcode::Mul, x, y) {
+            return res.raw().into();
+        }
+
+        let iv_x = self.simple_iv(ctx, x).unwrap();
+        let iv_y = self.simple_iv(ctx, y).unwrap();
+        let (pre, latch) = (iv_x.pre, iv_x.latch);
+
+        let a = self.iv_step(ctx, &iv_x);
+        let b = self.iv_step(ctx, &iv_y);
+        let x0 = if x == iv_x.header.into() {
+            iv_x.init
+        } else {
+            self.apply(ctx, Opcode::Add, iv_x.init, a, pre)
+        };
+        let y0 = if y == iv_y.header.into() {
+            iv_y.init
+        } else {
+            self.apply(ctx, Opcode::Add, iv_y.init, b, pre)
+        };
+
+        let q0 = self.apply(ctx, Opcode::Mul, x0, y0, pre);
+        let ab = self.apply(ctx, Opcode::Mul, a, b, pre);
+        let ay0 = self.apply(ctx, Opcode::Mul, a, y0, pre);
+        let bx0 = self.apply(ctx, Opcode::Mul, b, x0, pre);
+        let d0 = self.apply(ctx, Opcode::Add, ay0, bx0, pre);
+        let d0 = self.apply(ctx, Opcode::Add, d0, ab, pre);
+        let dd = self.apply(ctx, Opcode::Add, ab, ab, pre);
+
+        let mut b = self.builder(ctx, InsertPoint::After(iv_x.header));
+        let q = b.phi(&[(pre, q0), (latch, q0)]);
+        let d = b.phi(&[(pre, d0), (latch, d0)]);
+
+        let term = *latch.own(ctx).unwrap().ins().last().unwrap();
+        let mut b = self.builder(ctx, InsertPoint::Before(term));
+        let q1 = b.add(q.into(), d.into());
+        let d1 = b.add(d.into(), dd);
+        ctx.ins_set_op(q, 3, q1.into());
+        ctx.ins_set_op(d, 3, d1.into());
+
+        self.table.insert(ctx, Opcode::Mul, x, y, q.into());
+        q
+    }
+
+    // Build a new induction variable computing `iv op rc`
+    fn reduce_iv(
+        &mut self,
+        ctx: &mut Context,
+        op: Opcode,
+        iv: InstructionRef,
+        rc: ValueRef,
+    ) -> InstructionRef {
+        if let Some(res) = self.table.get(ctx, op, iv.into(), rc) {
+            return res.raw().into();
+        }
+
+        let mut clones = vec![];
+        let res = self.reduce(ctx, op, iv, rc, &mut clones);
+
+        let header = self.iv_header(iv.into()).unwrap();
+        let new_header = self.table.get(ctx, op, header.into(), rc).unwrap();
+        for clone in &clones {
+            self.ivs.add_iv(*clone, new_header.raw().into());
+        }
+        self.reduced.push(clones);
+        res
+    }
+
+    fn reduce(
+        &mut self,
+        ctx: &mut Context,
+        op: Opcode,
+        iv: InstructionRef,
+        rc: ValueRef,
+        clones: &mut Vec<InstructionRef>,
+    ) -> InstructionRef {
+        if let Some(res) = self.table.get(ctx, op, iv.into(), rc) {
+            return res.raw().into();
+        }
+
+        let header = self.iv_header(iv.into()).unwrap();
+        let iv_obj = iv.own(ctx).unwrap();
+        let iv_op = iv_obj.opcode();
+        let ops = iv_obj.val().ops().to_vec();
+
+        let res = self
+            .builder(ctx, InsertPoint::After(iv))
+            .def(iv_op, &ops[..]);
+        self.table.insert(ctx, op, iv.into(), rc, res.into());
+        clones.push(res);
+        self.edges.entry(iv).or_default().push((op, rc, res));
+
+        for (idx, o) in ops.iter().enumerate() {
+            if let ValueRefEnum::BB(_) = o.to_enum() {
+                continue;
+            }
+
+            if self.iv_header(*o) == Some(header) {
+                let new_o = self.reduce(ctx, op, o.raw().into(), rc, clones);
+                ctx.ins_set_op(res, idx, new_o.into());
+            } else if iv_op == Opcode::Phi {
+                let pred: BasicBlockRef = ops[idx - 1].raw().into();
+                let new_o = self.apply(ctx, op, *o, rc, pred);
+                ctx.ins_set_op(res, idx, new_o);
+            } else if op == Opcode::Mul {
+                let pred = self.preheader(ctx, header);
+                let new_o = self.apply(ctx, op, *o, rc, pred);
+                ctx.ins_set_op(res, idx, new_o);
+            }
+        }
+
+        res
+    }
+
+    // Compute `o1 op o2` at the end of block `at`
+    fn apply(
+        &mut self,
+        ctx: &mut Context,
+        op: Opcode,
+        o1: ValueRef,
+        o2: ValueRef,
+        at: BasicBlockRef,
+    ) -> ValueRef {
+        if let Some(header) = self.iv_header(o1) {
+            if self.is_region_const(ctx, o2, header) {
+                return self.reduce_iv(ctx, op, o1.raw().into(), o2).into();
+            }
+        }
+        if let Some(header) = self.iv_header(o2) {
+            if op != Opcode::Sub && self.is_region_const(ctx, o1, header) {
+                return self.reduce_iv(ctx, op, o2.raw().into(), o1).into();
+            }
+        }
+        if op == Opcode::Mul && self.is_poly(ctx, o1, o2) {
+            return self.reduce_poly(ctx, o1, o2).into();
+        }
+
+        if let Some(res) = fold(ctx, op, o1, o2) {
+            return res;
+        }
+
+        if let Some(res) = self.table.get(ctx, op, o1, o2) {
+            if self.is_available_at(ctx, res, at) {
+                return res;
+            }
+        }
+        if let Some(res) = self.find_existing(ctx, op, o1, o2, at) {
+            self.table.insert(ctx, op, o1, o2, res);
+            return res;
+        }
+
+        let term = *at.own(ctx).unwrap().ins().last().unwrap();
+        let res = self
+            .builder(ctx, InsertPoint::Before(term))
+            .binop(op, o1, o2);
+        self.table.insert(ctx, op, o1, o2, res.into());
+        res.into()
+    }
+
+    // Instruction of the function computing `o1 op o2`, defined at the end of
+    // block `at`
+    // It is found among the users of an operand that is not a constant
+    fn find_existing(
+        &self,
+        ctx: &Context,
+        op: Opcode,
+        o1: ValueRef,
+        o2: ValueRef,
+        at: BasicBlockRef,
+    ) -> Option<ValueRef> {
+        let key = (TableOperand::new(ctx, o1), TableOperand::new(ctx, o2));
+        let v = if const_val(ctx, o1).is_none() { o1 } else { o2 };
+        v.own(ctx)?.users().find(|user| {
+            let ins = match user.to_enum() {
+                ValueRefEnum::Ins(ins) => ins.own(ctx).unwrap(),
+                _ => return false,
+            };
+            if ins.opcode() != op || ins.parent().is_none() {
+                return false;
+            }
+            let ops = ins.val().ops();
+            let (a, b) = (
+                TableOperand::new(ctx, ops[0]),
+                TableOperand::new(ctx, ops[1]),
+            );
+            ((a, b) == key || (op.infos().is_commutative() && (b, a) == key))
+                && self.is_available_at(ctx, *user, at)
+        })
+    }
+
+    // Returns true if `v` is defined at the end of block `at`
+    fn is_available_at(&self, ctx: &Context, v: ValueRef, at: BasicBlockRef) -> bool {
+        match v.to_enum() {
+            ValueRefEnum::Ins(i) => {
+                let bb = i.own(ctx).unwrap().parent().unwrap();
+                self.ivs.dom().dominates(bb, at)
+            }
+            _ => true,
+        }
+    }
+
+    // Rewrite the exit tests `cmplt iv, rc` of the loops into comparisons of
+    // a reduced IV, if the IV of `iv` is dead afterwards
+    fn lftr(&mut self, ctx: &mut Context) {
+        for (cmp, lp) in self.exit_tests(ctx) {
+            for (iv_pos, rc_pos) in &[(0, 1), (1, 0)] {
+                let ops = cmp.own(ctx).unwrap().val().ops().to_vec();
+                let (iv, bound) = (ops[*iv_pos], ops[*rc_pos]);
+                let header = match self.iv_header(iv) {
+                    Some(header) => header,
+                    None => continue,
+                };
+                if header.own(ctx).unwrap().parent() != Some(lp)
+                    || !self.is_region_const(ctx, bound, header)
+                {
+                    continue;
+                }
+
+                if self.lftr_edge(ctx, iv.raw().into()).is_none() {
+                    continue;
+                }
+                // Testing a reduced IV only pays off if the old IV goes away
+                if !self.dies_without(ctx, iv, cmp) {
+                    self.remarks.skipped(
+                        PASS_NAME,
+                        &self.fun_name,
+                        &ins_name(ctx, cmp),
+                        cmp.own(ctx).unwrap().loc(),
+                        format!("kept test of {}, its IV has other uses", val_name(ctx, iv)),
+                    );
+                    break;
+                }
+
+                self.loc = cmp.own(ctx).unwrap().loc().cloned();
+                if let Some((new_iv, new_bound)) = self.follow_edges(ctx, iv.raw().into(), bound) {
+                    ctx.ins_set_op(cmp, *iv_pos, new_iv.into());
+                    ctx.ins_set_op(cmp, *rc_pos, new_bound);
+                    self.remarks.applied(
+                        PASS_NAME,
+                        &self.fun_name,
+                        &ins_name(ctx, cmp),
+                        cmp.own(ctx).unwrap().loc(),
+                        format!(
+                            "replaced test of {} by test of {} against {}",
+                            val_name(ctx, iv),
+                            val_name(ctx, new_iv.into()),
+                            val_name(ctx, new_bound)
+                        ),
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    // `cmplt` deciding a `bc` leaving a loop, with the header of the loop
+    // Other comparisons of an IV are left as they are
+    fn exit_tests(&self, ctx: &Context) -> Vec<(InstructionRef, BasicBlockRef)> {
+        let loops: Vec<(BasicBlockRef, HashSet<BasicBlockRef>)> = self
+            .ivs
+            .loops()
+            .iter()
+            .map(|lp| (*lp, self.ivs.loop_blocks(*lp)))
+            .collect();
+
+        let mut res = vec![];
+        for bb in self.fun.own(ctx).unwrap().bbs() {
+            let term = bb.own(ctx).unwrap().ins().last().unwrap();
+            let term = term.own(ctx).unwrap();
+            if term.opcode() != Opcode::Bc {
+                continue;
+            }
+            let ops = term.val().ops();
+            let cmp = match ops[0].to_enum() {
+                ValueRefEnum::Ins(cmp) if cmp.own(ctx).unwrap().opcode() == Opcode::Cmplt => cmp,
+                _ => continue,
+            };
+
+            for (lp, blocks) in &loops {
+                let is_exit = blocks.contains(bb)
+                    && ops[1..]
+                        .iter()
+                        .any(|target| !blocks.contains(&target.raw().into()));
+                if is_exit {
+                    res.push((cmp, *lp));
+                }
+            }
+        }
+        res
+    }
+
+    // First LFTR edge of `iv` preserving the order of the comparison
+    fn lftr_edge(
+        &self,
+        ctx: &Context,
+        iv: InstructionRef,
+    ) -> Option<(Opcode, ValueRef, InstructionRef)> {
+        self.edges.get(&iv).and_then(|edges| {
+            edges
+                .iter()
+                .find(|(op, rc, _)| *op != Opcode::Mul || const_val(ctx, *rc).unwrap_or(0) > 0)
+                .cloned()
+        })
+    }
+
+    // Follow the LFTR edges from `iv` to the last reduced IV
+    // Only edges preserving the order of the comparison are used
+    fn follow_edges(
+        &mut self,
+        ctx: &mut Context,
+        iv: InstructionRef,
+        bound: ValueRef,
+    ) -> Option<(InstructionRef, ValueRef)> {
+        let mut res = None;
+        let (mut iv, mut bound) = (iv, bound);
+
+        while let Some((op, rc, new_iv)) = self.lftr_edge(ctx, iv) {
+            let at = self.preheader(ctx, self.iv_header(new_iv.into()).unwrap());
+            bound = self.apply(ctx, op, bound, rc, at);
+            iv = new_iv;
+            res = Some((iv, bound));
+        }
+
+        res
+    }
+
+    // Remove the IVs only used by themselves, the original ones and the ones
+    // built by the reduction
+    // Removing an IV may leave dead another one using it, so the IVs are
+    // checked again until nothing changes
+    fn remove_dead_ivs(&mut self, ctx: &mut Context) {
+        let mut sccs = self.iv_sccs();
+        while self.remove_dead_sccs(ctx, &mut sccs) {}
+    }
+
+    fn iv_sccs(&self) -> Vec<Vec<InstructionRef>> {
+        let mut sccs: Vec<Vec<InstructionRef>> = self
+            .ivs
+            .sccs()
+            .iter()
+            .filter(|scc| self.ivs.is_iv(scc[0].into()))
+            .cloned()
+            .collect();
+        sccs.extend(self.reduced.iter().cloned());
+        sccs
+    }
+
+    // True if `remove_dead_ivs` would remove the IV of `iv` once `cmp` no
+    // longer uses it
+    fn dies_without(&self, ctx: &Context, iv: ValueRef, cmp: InstructionRef) -> bool {
+        let sccs = self.iv_sccs();
+        // Users going away: `cmp`, then the members of the dead IVs
+        let mut dead: HashSet<ValueRef> = HashSet::new();
+        dead.insert(cmp.into());
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for scc in &sccs {
+                if dead.contains(&scc[0].into()) {
+                    continue;
+                }
+                let is_live = scc.iter().any(|n| {
+                    n.own(ctx).unwrap().val().users().any(|user| {
+                        !dead.contains(&user) && !scc.iter().any(|x| ValueRef::from(*x) == user)
+                    })
+                });
+                if !is_live {
+                    dead.extend(scc.iter().map(|n| ValueRef::from(*n)));
+                    changed = true;
+                }
+            }
+        }
+        dead.contains(&iv)
+    }
+
+    // Returns true if an IV was removed
+    fn remove_dead_sccs(&mut self, ctx: &mut Context, sccs: &mut Vec<Vec<InstructionRef>>) -> bool {
+        let mut changed = false;
+        let mut idx = 0;
+        while idx < sccs.len() {
+            let scc = &sccs[idx];
+            let is_live = scc.iter().any(|n| {
+                n.own(ctx)
+                    .unwrap()
+                    .val()
+                    .users()
+                    .any(|user| !scc.iter().any(|x| ValueRef::from(*x) == user))
+            });
+            if !is_live {
+                let header = self.iv_header(scc[0].into()).unwrap();
+                self.remarks.applied(
+                    PASS_NAME,
+                    &self.fun_name,
+                    &ins_name(ctx, header),
+                    header.own(ctx).unwrap().loc(),
+                    "removed dead IV".to_string(),
+                );
+                ctx.erase_ins_group(&sccs.remove(idx));
+                changed = true;
+            } else {
+                idx += 1;
+            }
+        }
+        changed
+    }
+
+    // Block where values needed by the loop of `header` are computed
+    fn preheader(&self, ctx: &Context, header: InstructionRef) -> BasicBlockRef {
+        self.ivs
+            .dom()
+            .idom(header.own(ctx).unwrap().parent().unwrap())
+    }
+
+    fn iv_header(&self, v: ValueRef) -> Option<InstructionRef> {
+        self.ivs.iv_header(v)
+    }
+
+    fn is_region_const(&self, ctx: &Context, v: ValueRef, header: InstructionRef) -> bool {
+        let lp = header.own(ctx).unwrap().parent().unwrap();
+        self.ivs.is_region_const(ctx, v, lp)
+    }
+
+    // New values are named `osr.N`, with the location of the instruction being reduced
+    fn builder<'b>(&'b mut self, ctx: &'b mut Context, point: InsertPoint) -> IrBuilder<'b> {
+        let mut b = IrBuilder::with_names(ctx, &mut self.names);
+        b.set_prefix("osr.");
+        b.set_loc(self.loc.clone());
+        b.set_point(point);
+        b
+    }
+}
+
+// Constant folding and algebraic identities for `o1 op o2`
+fn fold(ctx: &mut Context, op: Opcode, o1: ValueRef, o2: ValueRef) -> Option<ValueRef> {
+    let c1 = const_val(ctx, o1);
+    let c2 = const_val(ctx, o2);
+
+    if let (Some(c1), Some(c2)) = (c1, c2) {
+        let res = op.eval(&[c1, c2])?;
+        return Some(ctx.make_const("", res).into());
+    }
+
+    match (op, c1, c2) {
+        (Opcode::Add, Some(0), _) => Some(o2),
+        (Opcode::Add, _, Some(0)) | (Opcode::Sub, _, Some(0)) => Some(o1),
+        (Opcode::Shl, _, Some(0)) | (Opcode::Shr, _, Some(0)) => Some(o1),
+        (Opcode::Mul, Some(1), _) => Some(o2),
+        (Opcode::Mul, _, Some(1)) => Some(o1),
+        (Opcode::Mul, Some(0), _) | (Opcode::Mul, _, Some(0)) => Some(ctx.make_const("", 0).into()),
+        _ => None,
+    }
+}
+
+fn const_val(ctx: &Context, v: ValueRef) -> Option<i64> {
+    match v.to_enum() {
+        ValueRefEnum::Const(c) => Some(c.own(ctx).unwrap().const_int()),
+        _ => None,
+    }
+}
+
+// Shift amount of a `shl` that can be reduced as a multiplication
+// `iv << 63` would need the negative factor `2^63`
+fn shift_amount(ctx: &Context, v: ValueRef) -> Option<i64> {
+    const_val(ctx, v).filter(|c| (0..63).contains(c))
+}
+
+fn ins_name(ctx: &Context, ins: InstructionRef) -> String {
+    let ins = ins.own(ctx).unwrap();
+    if ins.val().is_def() {
+        ins.val().name().to_string()
+    } else {
+        ins.opname().to_string()
+    }
+}
+
+// Comment `reduced from %a, %b` on the value replacing `%a` and `%b`
+// The comments of `n` are kept above the value
+fn note_reduced_from(ctx: &mut Context, res: InstructionRef, n: InstructionRef) {
+    let name = ins_name(ctx, n);
+    let n_comments = n.own(ctx).unwrap().comments().clone();
+    let comments = res.own_mut(ctx).unwrap().comments_mut();
+    for comm in n_comments.pre() {
+        comments.add_pre(comm.clone());
+    }
+    if !n_comments.eol().is_empty() {
+        comments.add_pre(n_comments.eol().to_string());
+    }
+    let eol = match comments.eol() {
+        "" => format!("reduced from %{}", name),
+        eol => format!("{}, %{}", eol, name),
+    };
+    comments.set_eol(eol);
+}
+
+fn val_name(ctx: &Context, v: ValueRef) -> String {
+    match v.to_enum() {
+        ValueRefEnum::Const(c) => c.own(ctx).unwrap().const_int().to_string(),
+        _ => format!("%{}", v.own(ctx).unwrap().name()),
+    }
+}
+
+pub fn run_fun(ctx: &mut Context, fun: FunctionRef, remarks: &mut Remarks) {
+    // New values of a loop are initialized in its preheader, the new
+    // preheaders are removed again when nothing was hoisted into them
+    let new_bbs = cfg_utils::insert_preheaders(ctx, fun);
+    OSR::new(ctx, fun, remarks).run(ctx);
+    for pre in new_bbs {
+        cfg_utils::remove_preheader(ctx, pre);
+    }
+}
+
+pub fn run(ctx: &mut Context, remarks: &mut Remarks) {
+    let funs: Vec<FunctionRef> = ctx.funs().collect();
+    for fun in funs {
+        if !fun.own(ctx).unwrap().is_decl() {
+            run_fun(ctx, fun, remarks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basicblock::BasicBlock;
+    use crate::checker;
+    use crate::gop;
+    use crate::instruction::Instruction;
+    use crate::loader;
+    use crate::test_utils::find_path;
+    use crate::visitor::{self, Visitor};
+
+    fn run_file(path: &str) -> Context {
+        run_file_remarks(path).0
+    }
+
+    fn run_file_remarks(path: &str) -> (Context, String) {
+        let path = find_path(path);
+
+        let mut ctx = Context::new();
+        let mut remarks = Remarks::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        run(&mut ctx, &mut remarks);
+        checker::check_code(&ctx);
+
+        let mut os = vec![];
+        remarks.dump_text(&mut os).unwrap();
+        (ctx, String::from_utf8(os).unwrap())
+    }
+
+    // Number of instructions with `opcode`, in the blocks named `bb_name` if any
+    struct OpCounter<'a> {
+        opcode: Opcode,
+        bb_name: Option<&'a str>,
+        in_bb: bool,
+        count: usize,
+    }
+
+    impl Visitor for OpCounter<'_> {
+        fn visit_bb(&mut self, _ctx: &Context, bb: &BasicBlock) {
+            self.in_bb = self.bb_name.is_none_or(|name| bb.val().name() == name);
+        }
+
+        fn visit_ins(&mut self, _ctx: &Context, ins: &Instruction) {
+            if self.in_bb && ins.opcode() == self.opcode {
+                self.count += 1;
+            }
+        }
+    }
+
+    fn count(ctx: &Context, bb_name: Option<&str>, opcode: Opcode) -> usize {
+        let mut counter = OpCounter {
+            opcode,
+            bb_name,
+            in_bb: false,
+            count: 0,
+        };
+        visitor::walk(ctx, &mut counter);
+        counter.count
+    }
+
+    fn count_ops(ctx: &Context, opcode: Opcode) -> usize {
+        count(ctx, None, opcode)
+    }
+
+    fn count_ops_in(ctx: &Context, bb_name: &str, opcode: Opcode) -> usize {
+        count(ctx, Some(bb_name), opcode)
+    }
+
+    #[test]
+    fn osr_fact_iter() {
+        // r1 = r * i: r is not a region constant, nothing to reduce
+        let ctx = run_file("examples/fact_iter.ir");
+        assert_eq!(count_ops(&ctx, Opcode::Mul), 1);
+        assert_eq!(count_ops(&ctx, Opcode::Phi), 4);
+
+        // Nothing was hoisted, no preheader is left
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(!out.contains("B0.loop"));
+    }
+
+    #[test]
+    fn osr_cycle1() {
+        let ctx = run_file("examples/cycle1.ir");
+        assert_eq!(count_ops(&ctx, Opcode::Mul), 4);
+        assert_eq!(count_ops(&ctx, Opcode::Phi), 4);
+    }
+
+    #[test]
+    fn osr_mul_loop() {
+        let ctx = run_file("examples/mul_loop.ir");
+        assert_eq!(count_ops_in(&ctx, "loop", Opcode::Mul), 0);
+        assert_eq!(count_ops(&ctx, Opcode::Mul), 1);
+        assert_eq!(count_ops(&ctx, Opcode::Phi), 4);
+    }
+
+    #[test]
+    fn osr_locs() {
+        // New IVs are located at the instruction they replace
+        let ctx = run_file("examples/mul_loop.ir");
+        let fun = ctx.funs().next().unwrap();
+        let mut found = false;
+        for bb in fun.own(&ctx).unwrap().bbs() {
+            for ins in bb.own(&ctx).unwrap().ins() {
+                let ins = ins.own(&ctx).unwrap();
+                if ins.val().name() == "osr.0" {
+                    assert_eq!(ins.loc().unwrap().line(), 11);
+                    found = true;
+                }
+            }
+        }
+        assert!(found);
+    }
+
+    #[test]
+    fn osr_names_taken() {
+        // The argument already has the name of the first new IV
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "names.ir",
+            "f:\n.fun i64, %osr.0\n\nB0:\n\tb @loop\n\nloop:\n\tphi %i, @B0, 0, @loop, %i1\n\tmul %t, %i, %osr.0\n\tadd %i1, %i, 1\n\tcmplt %c, %i1, 10\n\tbc %c, @loop, @end\n\nend:\n\tret %t\n",
+        );
+        loader::load_gop(&mut ctx, &gmod);
+        run(&mut ctx, &mut Remarks::new());
+        checker::check_code(&ctx);
+
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("phi %osr.1, @B0, 0, @loop, %osr.2 ; reduced from %t"));
+        assert!(out.contains("add %osr.2, %osr.1, %osr.0"));
+    }
+
+    #[test]
+    fn osr_mul_shared() {
+        let ctx = run_file("examples/mul_shared.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert_eq!(count_ops_in(&ctx, "low", Opcode::Mul), 0);
+        assert_eq!(count_ops_in(&ctx, "high", Opcode::Mul), 0);
+        assert!(out.contains("phi %t, @low, %osr.0, @high, %osr.0"));
+    }
+
+    #[test]
+    fn lftr_exit_test_only() {
+        // %i is also compared by %c0, which doesn't leave the loop: %i stays
+        // alive for %c0, so testing a reduced IV in %c1 would only add one
+        let (ctx, remarks) = run_file_remarks("examples/mul_shared.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("cmplt %c0, %i, 5"));
+        assert!(out.contains("cmplt %c1, %i1, %n"));
+        assert_eq!(count_ops_in(&ctx, "loop", Opcode::Phi), 3);
+        assert!(!remarks.contains("%c0: replaced test"));
+        assert!(remarks.contains("%c1: skipped: kept test of %i1, its IV has other uses\n"));
+        assert!(!remarks.contains("removed dead IV"));
+    }
+
+    #[test]
+    fn osr_poly() {
+        let (ctx, remarks) = run_file_remarks("examples/poly.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert_eq!(count_ops(&ctx, Opcode::Mul), 0);
+        assert_eq!(count_ops_in(&ctx, "loop", Opcode::Phi), 6);
+        assert!(out.contains("phi %osr.0, @B0, 0, @loop, %osr.2"));
+        assert!(out.contains("phi %osr.1, @B0, 1, @loop, %osr.3"));
+        assert!(out.contains("add %osr.2, %osr.0, %osr.1"));
+        assert!(out.contains("add %osr.3, %osr.1, 2"));
+        assert!(out.contains("phi %osr.5, @B0, 2, @loop, %osr.7"));
+        assert!(remarks.contains("%sq: reduced into polynomial recurrence %osr.0"));
+    }
+
+    #[test]
+    fn osr_comments() {
+        // Hand-written comments are kept, new IVs tell what they replace
+        let ctx = run_file("examples/comments.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("\t; %i counts from 0 to n - 1\n\tphi %i,"));
+        assert!(out.contains(
+            "\t; polynomial of the IV\n\tphi %osr.0, @B0, 0, @loop, %osr.2 ; reduced from %sq\n"
+        ));
+        assert!(out.contains("call @_std_print, %v ; 285\n"));
+    }
+
+    #[test]
+    fn osr_array() {
+        let ctx = run_file("examples/array.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert_eq!(count_ops_in(&ctx, "init", Opcode::Mul), 0);
+        assert_eq!(count_ops_in(&ctx, "walk", Opcode::Mul), 0);
+
+        // The byte offsets of both walks are IVs stepping by 8
+        assert!(out.contains("offset %p, %a, %osr.0"));
+        assert!(out.contains("add %osr.1, %osr.0, 8"));
+        assert!(out.contains("offset %q, %a, %osr.2"));
+        assert!(out.contains("add %osr.3, %osr.2, 8"));
+
+        // The bound of the walk is %n * 8, already computed as %size
+        assert!(out.contains("cmplt %d, %osr.3, %size"));
+        assert_eq!(count_ops(&ctx, Opcode::Mul), 1);
+        assert!(!out.contains("init.walk"));
+    }
+
+    #[test]
+    fn osr_nested() {
+        let ctx = run_file("examples/nested.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert_eq!(count_ops_in(&ctx, "outer", Opcode::Mul), 0);
+        assert_eq!(count_ops_in(&ctx, "inner", Opcode::Mul), 0);
+        assert_eq!(count_ops_in(&ctx, "outer_latch", Opcode::Mul), 0);
+
+        // j * i starts at i * i, and is incremented by the outer IV 2 * i
+        assert!(out.contains("phi %osr.0, @outer, %osr.1, @inner, %osr.5"));
+        assert!(out.contains("add %osr.5, %osr.0, %osr.6"));
+        assert!(out.contains("phi %osr.6, @B0, 0, @outer_latch, %osr.7"));
+        assert!(out.contains("add %osr.7, %osr.6, 2"));
+        assert!(out.contains("phi %osr.1, @B0, 0, @outer_latch, %osr.3"));
+
+        // %i is still the initial value of %j, the outer exit test keeps it
+        assert!(out.contains("cmplt %c2, %i1, %n"));
+        assert_eq!(count_ops_in(&ctx, "outer", Opcode::Phi), 5);
+    }
+
+    #[test]
+    fn osr_shl() {
+        let (ctx, remarks) = run_file_remarks("examples/index.ir");
+        assert_eq!(count_ops_in(&ctx, "loop", Opcode::Mul), 0);
+        assert_eq!(count_ops_in(&ctx, "loop", Opcode::Shl), 0);
+        assert!(remarks.contains("osr: addrs: %off4: reduced into new IV %osr.4\n"));
+
+        // `%p + (%i << 2)` steps by 4, `%p + %i * 8` by 8
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("add %osr.7, %osr.6, 4"));
+        assert!(out.contains("add %osr.3, %osr.2, 8"));
+    }
+
+    #[test]
+    fn osr_dead_reduced_ivs() {
+        // The offsets reduced into new IVs are only used to build the
+        // addresses, their IVs are dead once the addresses are reduced
+        let (ctx, remarks) = run_file_remarks("examples/index.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert_eq!(count_ops_in(&ctx, "loop", Opcode::Phi), 3);
+        assert!(!out.contains("%osr.0,"));
+        assert!(!out.contains("%osr.4,"));
+        assert!(remarks.contains("osr: addrs: %osr.0: removed dead IV\n"));
+        assert!(remarks.contains("osr: addrs: %osr.4: removed dead IV\n"));
+    }
+
+    #[test]
+    fn remarks_shift_amount() {
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "shifts.ir",
+            "f:\n.fun i64, %n\n\nB0:\n\tb @loop\n\nloop:\n\tphi %i, @B0, 0, @loop, %i1\n\tshl %t, %i, 63\n\tshl %u, %i, 70\n\tshl %v, %i, %n\n\tadd %s, %t, %u\n\tadd %s1, %s, %v\n\tadd %i1, %i, 1\n\tcmplt %c, %i1, %n\n\tbc %c, @loop, @end\n\nend:\n\tret %s1\n",
+        );
+        loader::load_gop(&mut ctx, &gmod);
+        let mut remarks = Remarks::new();
+        run(&mut ctx, &mut remarks);
+        checker::check_code(&ctx);
+
+        let mut os = vec![];
+        remarks.dump_text(&mut os).unwrap();
+        let remarks = String::from_utf8(os).unwrap();
+        assert!(remarks.contains("osr: f: %t: skipped: shift amount 63 is out of range\n"));
+        assert!(remarks.contains("osr: f: %u: skipped: shift amount 70 is out of range\n"));
+        assert!(remarks.contains("osr: f: %v: skipped: shift amount %n is not a constant\n"));
+    }
+
+    #[test]
+    fn remarks_fact_iter() {
+        let (_, remarks) = run_file_remarks("examples/fact_iter.ir");
+        assert_eq!(
+            remarks,
+            format!(
+                "{}:11:2: osr: fact: %r1: skipped: IV has non-linear update (%r)\n",
+                find_path("examples/fact_iter.ir")
+            )
+        );
+    }
+
+    #[test]
+    fn remarks_mul_loop() {
+        let (_, remarks) = run_file_remarks("examples/mul_loop.ir");
+        assert_eq!(
+            remarks,
+            format!(
+                "{0}:11:2: osr: sum: %t0: reduced into new IV %osr.0
+{0}:12:2: osr: sum: %t1: reduced into new IV %osr.2
+{0}:16:2: osr: sum: %c1: replaced test of %i1 by test of %osr.3 against %osr.4
+{0}:9:2: osr: sum: %i: removed dead IV
+",
+                find_path("examples/mul_loop.ir")
+            )
+        );
+    }
+
+    #[test]
+    fn lftr_mul_loop() {
+        let ctx = run_file("examples/mul_loop.ir");
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(!out.contains("%i,"));
+        assert!(!out.contains("%i1"));
+        assert!(out.contains("mul %osr.4, %n, 3"));
+        assert!(out.contains("cmplt %c1, %osr.3, %osr.4"));
+    }
+}
@@ -0,0 +1,152 @@
+// Peephole optimizations
+// Rewrite single instructions into cheaper equivalents:
+//   mul x, 2^k  =>  shl x, k
+//   mul 2^k, x  =>  shl x, k
+
+use crate::context::Context;
+use crate::ir_builder::IrBuilder;
+use crate::isa::Opcode;
+use crate::names::NameAllocator;
+use crate::remarks::Remarks;
+use crate::valueref::{FunctionRef, InstructionRef, ValueRef, ValueRefEnum};
+use crate::visitor::{self, Action, Rewriter};
+
+const PASS_NAME: &str = "peephole";
+
+// Returns `k` if `v` is the constant `2^k`, with k > 0
+fn const_log2(ctx: &Context, v: ValueRef) -> Option<u32> {
+    match v.to_enum() {
+        ValueRefEnum::Const(c) => {
+            let c = c.own(ctx).unwrap().const_int();
+            if c > 1 && c & (c - 1) == 0 {
+                Some(c.trailing_zeros())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// New instructions replace the ones they rewrite, with their name,
+// location and comments
+struct Peephole<'a> {
+    fun_name: String,
+    names: NameAllocator,
+    remarks: &'a mut Remarks,
+}
+
+impl Peephole<'_> {
+    fn mul_to_shl(&mut self, ctx: &mut Context, ins: InstructionRef) -> Option<InstructionRef> {
+        let ops = ins.own(ctx).unwrap().val().ops().to_vec();
+        let (x, k) = match (const_log2(ctx, ops[0]), const_log2(ctx, ops[1])) {
+            (_, Some(k)) => (ops[0], k),
+            (Some(k), None) => (ops[1], k),
+            (None, None) => return None,
+        };
+
+        let ins_obj = ins.own(ctx).unwrap();
+        let name = ins_obj.val().name().to_string();
+        let loc = ins_obj.loc().cloned();
+        let comments = ins_obj.comments().clone();
+        self.remarks.applied(
+            PASS_NAME,
+            &self.fun_name,
+            &name,
+            loc.as_ref(),
+            format!("replaced mul by {} with shl by {}", 1i64 << k, k),
+        );
+
+        // The `shl` takes over the name of the `mul`
+        ins.own_mut(ctx).unwrap().val_mut().rename("");
+        self.names.remove(&name);
+        let mut b = IrBuilder::with_names(ctx, &mut self.names);
+        b.set_loc(loc);
+        b.set_insert_before(ins);
+        let k_val = b.const_int(k as i64);
+        let shl = b.named(&name).shl(x, k_val);
+        *shl.own_mut(ctx).unwrap().comments_mut() = comments;
+        Some(shl)
+    }
+}
+
+impl Rewriter for Peephole<'_> {
+    fn rewrite(&mut self, ctx: &mut Context, ins: InstructionRef) -> Action {
+        if ins.own(ctx).unwrap().opcode() == Opcode::Mul {
+            if let Some(shl) = self.mul_to_shl(ctx, ins) {
+                return Action::Replace(shl.into());
+            }
+        }
+        Action::Keep
+    }
+}
+
+pub fn run_fun(ctx: &mut Context, fun: FunctionRef, remarks: &mut Remarks) {
+    let mut peephole = Peephole {
+        fun_name: fun.own(ctx).unwrap().val().name().to_string(),
+        names: NameAllocator::of_values(ctx, fun),
+        remarks,
+    };
+    visitor::rewrite_fun(ctx, fun, &mut peephole);
+}
+
+pub fn run(ctx: &mut Context, remarks: &mut Remarks) {
+    let funs: Vec<FunctionRef> = ctx.funs().collect();
+    for fun in funs {
+        if !fun.own(ctx).unwrap().is_decl() {
+            run_fun(ctx, fun, remarks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker;
+    use crate::gop;
+    use crate::loader;
+    use crate::test_utils::find_path;
+
+    fn run_file(path: &str) -> (String, String) {
+        let path = find_path(path);
+
+        let mut ctx = Context::new();
+        let mut remarks = Remarks::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        run(&mut ctx, &mut remarks);
+        checker::check_code(&ctx);
+
+        let mut os = vec![];
+        remarks.dump_text(&mut os).unwrap();
+        (
+            format!("{}", loader::build_gop(&ctx)),
+            String::from_utf8(os).unwrap(),
+        )
+    }
+
+    #[test]
+    fn peephole_mul_shared() {
+        let (out, remarks) = run_file("examples/mul_shared.ir");
+        assert!(out.contains("shl %t0, %i, 2"));
+        assert!(out.contains("shl %t1, %i, 2"));
+        assert!(!out.contains("mul"));
+        assert_eq!(
+            remarks,
+            format!(
+                "{0}:14:2: peephole: sum: %t0: replaced mul by 4 with shl by 2
+{0}:18:2: peephole: sum: %t1: replaced mul by 4 with shl by 2
+",
+                find_path("examples/mul_shared.ir")
+            )
+        );
+    }
+
+    #[test]
+    fn peephole_mul_loop() {
+        // Neither 3 nor %k is a power of two
+        let (out, remarks) = run_file("examples/mul_loop.ir");
+        assert!(out.contains("mul %t0, %i, %k"));
+        assert!(out.contains("mul %t1, 3, %i"));
+        assert!(remarks.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use crate::instruction::Instruction;
+use crate::isa::Opcode;
+use crate::valueref::{BasicBlockRef, ValueRef};
+
+// View of a phi instruction as its incoming edges
+// Operands are stored as interleaved `@bb, value` pairs: the block of
+// incoming edge `k` is operand `2k`, its value operand `2k + 1`
+pub struct Phi<'a> {
+    ins: &'a Instruction,
+}
+
+impl<'a> Phi<'a> {
+    pub fn new(ins: &'a Instruction) -> Option<Phi<'a>> {
+        if ins.opcode() == Opcode::Phi {
+            Some(Phi { ins })
+        } else {
+            None
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn ins(&self) -> &'a Instruction {
+        self.ins
+    }
+
+    pub fn incoming(&self) -> impl Iterator<Item = (BasicBlockRef, ValueRef)> + 'a {
+        self.ins
+            .val()
+            .ops()
+            .chunks(2)
+            .map(|pair| (pair[0].raw().into(), pair[1]))
+    }
+
+    #[allow(dead_code)]
+    pub fn incoming_count(&self) -> usize {
+        self.ins.val().ops().len() / 2
+    }
+
+    pub fn incoming_blocks(&self) -> impl Iterator<Item = BasicBlockRef> + 'a {
+        self.incoming().map(|(bb, _)| bb)
+    }
+
+    pub fn incoming_value(&self, bb: BasicBlockRef) -> Option<ValueRef> {
+        self.value_idx(bb).map(|idx| self.ins.val().ops()[idx])
+    }
+
+    // Operand index of the value incoming from `bb`
+    pub fn value_idx(&self, bb: BasicBlockRef) -> Option<usize> {
+        self.incoming_blocks()
+            .position(|x| x == bb)
+            .map(|k| 2 * k + 1)
+    }
+}
@@ -0,0 +1,224 @@
+// Optimization remarks
+// Passes record what they did, or why they did nothing, for each instruction
+
+use crate::debug_loc::{loc_prefix, DebugLoc};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemarkKind {
+    Applied,
+    Skipped,
+}
+
+impl RemarkKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RemarkKind::Applied => "applied",
+            RemarkKind::Skipped => "skipped",
+        }
+    }
+}
+
+pub struct Remark {
+    pass: &'static str,
+    fun: String,
+    ins: String,
+    loc: Option<DebugLoc>,
+    kind: RemarkKind,
+    msg: String,
+}
+
+impl Remark {
+    #[allow(dead_code)]
+    pub fn pass(&self) -> &'static str {
+        self.pass
+    }
+
+    #[allow(dead_code)]
+    pub fn fun(&self) -> &str {
+        &self.fun
+    }
+
+    #[allow(dead_code)]
+    pub fn ins(&self) -> &str {
+        &self.ins
+    }
+
+    #[allow(dead_code)]
+    pub fn loc(&self) -> Option<&DebugLoc> {
+        self.loc.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn kind(&self) -> RemarkKind {
+        self.kind
+    }
+
+    #[allow(dead_code)]
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn to_json(&self) -> String {
+        let loc = match &self.loc {
+            Some(loc) => format!("\"{}\"", json_escape(&loc.to_string())),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"pass\": \"{}\", \"function\": \"{}\", \"instruction\": \"{}\", \"location\": {}, \"kind\": \"{}\", \"message\": \"{}\"}}",
+            json_escape(self.pass),
+            json_escape(&self.fun),
+            json_escape(&self.ins),
+            loc,
+            self.kind.name(),
+            json_escape(&self.msg)
+        )
+    }
+}
+
+impl fmt::Display for Remark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", loc_prefix(self.loc.as_ref()))?;
+        match self.kind {
+            RemarkKind::Applied => write!(
+                f,
+                "{}: {}: %{}: {}",
+                self.pass, self.fun, self.ins, self.msg
+            ),
+            RemarkKind::Skipped => write!(
+                f,
+                "{}: {}: %{}: skipped: {}",
+                self.pass, self.fun, self.ins, self.msg
+            ),
+        }
+    }
+}
+
+pub struct Remarks {
+    list: Vec<Remark>,
+}
+
+impl Remarks {
+    pub fn new() -> Remarks {
+        Remarks { list: vec![] }
+    }
+
+    #[allow(dead_code)]
+    pub fn list(&self) -> &[Remark] {
+        &self.list[..]
+    }
+
+    pub fn applied(
+        &mut self,
+        pass: &'static str,
+        fun: &str,
+        ins: &str,
+        loc: Option<&DebugLoc>,
+        msg: String,
+    ) {
+        self.add(pass, fun, ins, loc, RemarkKind::Applied, msg);
+    }
+
+    pub fn skipped(
+        &mut self,
+        pass: &'static str,
+        fun: &str,
+        ins: &str,
+        loc: Option<&DebugLoc>,
+        msg: String,
+    ) {
+        self.add(pass, fun, ins, loc, RemarkKind::Skipped, msg);
+    }
+
+    pub fn dump_text<T: std::io::Write>(&self, os: &mut T) -> std::io::Result<()> {
+        for r in &self.list {
+            writeln!(os, "{}", r)?;
+        }
+        Ok(())
+    }
+
+    // One JSON object per line
+    pub fn dump_json<T: std::io::Write>(&self, os: &mut T) -> std::io::Result<()> {
+        for r in &self.list {
+            writeln!(os, "{}", r.to_json())?;
+        }
+        Ok(())
+    }
+
+    fn add(
+        &mut self,
+        pass: &'static str,
+        fun: &str,
+        ins: &str,
+        loc: Option<&DebugLoc>,
+        kind: RemarkKind,
+        msg: String,
+    ) {
+        self.list.push(Remark {
+            pass,
+            fun: fun.to_string(),
+            ins: ins.to_string(),
+            loc: loc.cloned(),
+            kind,
+            msg,
+        });
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut res = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remarks_format() {
+        let mut remarks = Remarks::new();
+        let loc = DebugLoc::new("foo.ir".into(), 4, 5);
+        remarks.applied(
+            "osr",
+            "foo",
+            "r1",
+            Some(&loc),
+            "reduced into new IV %osr.3".to_string(),
+        );
+        remarks.skipped(
+            "osr",
+            "foo",
+            "t",
+            None,
+            "operand \"%x\" not a region constant".to_string(),
+        );
+
+        let mut text = vec![];
+        remarks.dump_text(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "foo.ir:4:5: osr: foo: %r1: reduced into new IV %osr.3
+osr: foo: %t: skipped: operand \"%x\" not a region constant
+"
+        );
+
+        let mut json = vec![];
+        remarks.dump_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"pass\": \"osr\", \"function\": \"foo\", \"instruction\": \"r1\", \"location\": \"foo.ir:4:5\", \"kind\": \"applied\", \"message\": \"reduced into new IV %osr.3\"}
+{\"pass\": \"osr\", \"function\": \"foo\", \"instruction\": \"t\", \"location\": null, \"kind\": \"skipped\", \"message\": \"operand \\\"%x\\\" not a region constant\"}
+"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::context::Context;
+use crate::valueref::{FunctionRef, InstructionRef, ValueRefEnum};
+use crate::vertex_adapter::VertexAdapter;
+
+// SSA graph of a function
+// One vertex per instruction, with an edge from each instruction to the
+// instructions it uses as operands (def-use chains walked backward)
+pub struct SSAGraph {
+    #[allow(dead_code)]
+    fun: FunctionRef,
+    va: VertexAdapter<InstructionRef>,
+    ops: Vec<Vec<usize>>,
+    users: Vec<Vec<usize>>,
+    names: Vec<String>,
+}
+
+impl SSAGraph {
+    pub fn new(ctx: &Context, fun: FunctionRef) -> SSAGraph {
+        let fun_obj = fun.own(ctx).unwrap();
+        let mut all_ins = vec![];
+        for bb in fun_obj.bbs() {
+            all_ins.extend_from_slice(bb.own(ctx).unwrap().ins());
+        }
+
+        let va = VertexAdapter::new(&all_ins[..]);
+        let mut res = SSAGraph {
+            fun,
+            ops: vec![vec![]; va.count()],
+            users: vec![vec![]; va.count()],
+            names: vec![String::new(); va.count()],
+            va,
+        };
+        res.prepare(ctx);
+        res
+    }
+
+    #[allow(dead_code)]
+    pub fn fun(&self) -> FunctionRef {
+        self.fun
+    }
+
+    #[allow(dead_code)]
+    pub fn va(&self) -> &VertexAdapter<InstructionRef> {
+        &self.va
+    }
+
+    pub fn ops<'a>(&'a self, ins: InstructionRef) -> impl Iterator<Item = InstructionRef> + 'a {
+        self.ops[self.va.o2v(ins)]
+            .iter()
+            .map(move |v| self.va.v2o(*v))
+    }
+
+    #[allow(dead_code)]
+    pub fn users<'a>(&'a self, ins: InstructionRef) -> impl Iterator<Item = InstructionRef> + 'a {
+        self.users[self.va.o2v(ins)]
+            .iter()
+            .map(move |v| self.va.v2o(*v))
+    }
+
+    // Strongly connected components, in the order Tarjan's algorithm
+    // discovers them: every SCC comes after the SCCs of its operands
+    pub fn sccs(&self) -> Vec<Vec<InstructionRef>> {
+        let mut tarjan = Tarjan::new(self.va.count());
+        for u in 0..self.va.count() {
+            if tarjan.num[u] == UNDEF {
+                tarjan.dfs(self, u);
+            }
+        }
+
+        tarjan
+            .res
+            .iter()
+            .map(|scc| scc.iter().map(|v| self.va.v2o(*v)).collect())
+            .collect()
+    }
+
+    // SCCs with more than one member, or with a self-loop
+    pub fn cycles(&self) -> Vec<Vec<InstructionRef>> {
+        self.sccs()
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || self.ops(scc[0]).any(|x| x == scc[0]))
+            .collect()
+    }
+
+    // Dot file, with the cycles grouped in clusters
+    pub fn dump_tree<T: std::io::Write>(&self, os: &mut T) -> std::io::Result<()> {
+        writeln!(os, "digraph G {{")?;
+
+        for (idx, scc) in self.cycles().iter().enumerate() {
+            writeln!(os, "  subgraph cluster_{} {{", idx)?;
+            for ins in scc {
+                write!(os, "  {};", self.va.o2v(*ins))?;
+            }
+            writeln!(os, "  }}")?;
+        }
+
+        for u in 0..self.va.count() {
+            writeln!(os, "  {} [ label=\"{}\" ];", u, self.names[u])?;
+        }
+
+        for u in 0..self.va.count() {
+            for v in &self.ops[u] {
+                writeln!(os, "  {} -> {}", u, v)?;
+            }
+        }
+
+        writeln!(os, "}}")
+    }
+
+    pub fn save_tree(&self, path: &str) {
+        let os = std::fs::File::create(path).expect("Failed to write tree file");
+        let mut os = std::io::BufWriter::new(&os);
+        self.dump_tree(&mut os).expect("Failed to write tree file");
+    }
+
+    fn prepare(&mut self, ctx: &Context) {
+        for u in 0..self.va.count() {
+            let ins = self.va.v2o(u).own(ctx).unwrap();
+            self.names[u] = if ins.val().is_def() {
+                ins.val().name().to_string()
+            } else {
+                ins.opname().to_string()
+            };
+
+            for op in ins.val().ops() {
+                if let ValueRefEnum::Ins(op) = op.to_enum() {
+                    let v = self.va.o2v(op);
+                    self.ops[u].push(v);
+                    self.users[v].push(u);
+                }
+            }
+        }
+    }
+}
+
+const UNDEF: usize = usize::MAX;
+
+struct Tarjan {
+    next_num: usize,
+    num: Vec<usize>,
+    low: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    res: Vec<Vec<usize>>,
+}
+
+impl Tarjan {
+    fn new(v: usize) -> Tarjan {
+        Tarjan {
+            next_num: 0,
+            num: vec![UNDEF; v],
+            low: vec![UNDEF; v],
+            stack: vec![],
+            on_stack: vec![false; v],
+            res: vec![],
+        }
+    }
+
+    fn visit(&mut self, u: usize) {
+        self.num[u] = self.next_num;
+        self.low[u] = self.next_num;
+        self.next_num += 1;
+        self.stack.push(u);
+        self.on_stack[u] = true;
+    }
+
+    // Iterative, def-use chains can be longer than the call stack allows
+    // Each frame is a vertex and the index of its next operand to explore
+    fn dfs(&mut self, g: &SSAGraph, root: usize) {
+        self.visit(root);
+        let mut frames = vec![(root, 0)];
+
+        while let Some((u, next)) = frames.last_mut() {
+            let u = *u;
+            if let Some(v) = g.ops[u].get(*next).copied() {
+                *next += 1;
+                if self.num[v] == UNDEF {
+                    self.visit(v);
+                    frames.push((v, 0));
+                } else if self.num[v] < self.num[u] && self.on_stack[v] {
+                    self.low[u] = self.low[u].min(self.num[v]);
+                }
+                continue;
+            }
+
+            frames.pop();
+            if let Some((parent, _)) = frames.last() {
+                self.low[*parent] = self.low[*parent].min(self.low[u]);
+            }
+
+            if self.low[u] == self.num[u] {
+                let mut scc = vec![];
+                loop {
+                    let v = self.stack.pop().unwrap();
+                    self.on_stack[v] = false;
+                    scc.push(v);
+                    if v == u {
+                        break;
+                    }
+                }
+                self.res.push(scc);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gop;
+    use crate::isa::Opcode;
+    use crate::loader;
+    use crate::test_utils::find_path;
+    use crate::valueref::ValueRef;
+
+    fn cycles_names(path: &str) -> Vec<Vec<String>> {
+        let path = find_path(path);
+
+        let mut ctx = Context::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        let fun = ctx.funs().next().unwrap();
+        let g = SSAGraph::new(&ctx, fun);
+        g.cycles()
+            .iter()
+            .map(|scc| {
+                let mut names: Vec<String> = scc
+                    .iter()
+                    .map(|ins| ins.own(&ctx).unwrap().val().name().to_string())
+                    .collect();
+                names.sort();
+                names
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ssa_fact_iter() {
+        let cycles = cycles_names("examples/fact_iter.ir");
+        assert_eq!(cycles.len(), 2);
+        assert!(cycles.contains(&vec!["r".to_string(), "r1".to_string()]));
+        assert!(cycles.contains(&vec!["i".to_string(), "i1".to_string()]));
+    }
+
+    // A chain of 100k adds, deeper than a recursive DFS could go
+    #[test]
+    fn ssa_long_chain() {
+        let mut ctx = Context::new();
+        let fun = ctx.make_fun("f", 1, false);
+        let bb = ctx.make_bb("B0");
+        ctx.bb_insert_in(bb, fun);
+        let mut prev: ValueRef = fun.own(&ctx).unwrap().args()[0].into();
+        for idx in 0..100_000 {
+            let ins = ctx.make_ins(&format!("a{}", idx), Opcode::Add, true, &[prev, prev]);
+            ctx.ins_insert_in(ins, bb);
+            prev = ins.into();
+        }
+
+        let g = SSAGraph::new(&ctx, fun);
+        assert_eq!(g.sccs().len(), 100_000);
+        assert!(g.cycles().is_empty());
+    }
+
+    #[test]
+    fn ssa_cycle1() {
+        let cycles = cycles_names("examples/cycle1.ir");
+        assert_eq!(cycles.len(), 2);
+        assert!(cycles.contains(&vec!["i".to_string(), "i2".to_string()]));
+        assert!(cycles.iter().any(|scc| scc.len() == 11));
+    }
+}
@@ -0,0 +1,12 @@
+// Helpers shared by the tests
+
+use std::path::Path;
+
+// Path of a file of the repository, e.g. `examples/fact_iter.ir`
+pub fn find_path(path: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(path)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+
+// Types of values and function results
+// `int` is accepted as an alias of `i64` in gop files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Void,
+    I64,
+    I1,
+    Ptr, // address of a byte in memory
+}
+
+impl Type {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Void => "void",
+            Type::I64 => "i64",
+            Type::I1 => "i1",
+            Type::Ptr => "ptr",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Type> {
+        match name {
+            "void" => Some(Type::Void),
+            "i64" | "int" => Some(Type::I64),
+            "i1" => Some(Type::I1),
+            "ptr" => Some(Type::Ptr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -1,11 +1,31 @@
-use crate::context::Context;
 use crate::valueref::ValueRef;
 
+// Operand `idx` of instruction `user`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Use {
+    user: ValueRef,
+    idx: usize,
+}
+
+impl Use {
+    pub fn new(user: ValueRef, idx: usize) -> Use {
+        Use { user, idx }
+    }
+
+    pub fn user(&self) -> ValueRef {
+        self.user
+    }
+
+    pub fn idx(&self) -> usize {
+        self.idx
+    }
+}
+
 pub struct Value {
     name: String,
     id: ValueRef,
     is_def: bool,
-    users: Vec<ValueRef>,
+    uses: Vec<Use>,
     ops: Vec<ValueRef>,
 }
 
@@ -15,7 +35,7 @@ impl Value {
             name: name.to_string(),
             id,
             is_def,
-            users: vec![],
+            uses: vec![],
             ops: ops.to_vec(),
         }
     }
@@ -36,12 +56,17 @@ impl Value {
         self.is_def
     }
 
-    pub fn users(&self) -> &[ValueRef] {
-        &self.users[..]
+    pub fn uses(&self) -> impl Iterator<Item = Use> + '_ {
+        self.uses.iter().copied()
     }
 
-    pub fn users_mut(&mut self) -> &mut [ValueRef] {
-        &mut self.users[..]
+    // User of every use, a user appears once per operand
+    pub fn users(&self) -> impl Iterator<Item = ValueRef> + '_ {
+        self.uses.iter().map(|u| u.user)
+    }
+
+    pub fn has_uses(&self) -> bool {
+        !self.uses.is_empty()
     }
 
     pub fn ops(&self) -> &[ValueRef] {
@@ -52,35 +77,32 @@ impl Value {
         &mut self.ops[..]
     }
 
-    pub fn users_del(&mut self, v: ValueRef) {
-        self.users.retain(|x| *x != v);
+    pub fn set_ops(&mut self, ops: &[ValueRef]) {
+        self.ops = ops.to_vec();
     }
 
-    pub fn users_add(&mut self, v: ValueRef) {
-        if !self.users.iter().find(|x| **x == v).is_some() {
-            self.users.push(v)
+    pub fn uses_del(&mut self, u: Use) {
+        if let Some(pos) = self.uses.iter().position(|x| *x == u) {
+            self.uses.swap_remove(pos);
         }
     }
 
-    pub fn has_op(&self, v: ValueRef) -> bool {
-        self.ops.iter().find(|x| **x == v).is_some()
+    pub fn uses_add(&mut self, u: Use) {
+        self.uses.push(u)
     }
 
-    pub fn set_op(&mut self, ctx: &mut Context, idx: usize, val: ValueRef) {
-        let old_val = self.ops[idx];
-        if old_val == val {
-            return;
-        }
-
-        if let Some(old_val) = old_val.own_mut(ctx) {
-            old_val.users.retain(|x| *x != self.id);
-        }
+    // Remove all the uses at once, to give them to another value
+    pub fn uses_take(&mut self) -> Vec<Use> {
+        std::mem::take(&mut self.uses)
+    }
 
-        if let Some(val) = val.own_mut(ctx) {
-            val.users.push(self.id);
-        }
+    pub fn uses_extend(&mut self, uses: &[Use]) {
+        self.uses.extend_from_slice(uses)
+    }
 
-        self.ops[idx] = val;
+    #[allow(dead_code)]
+    pub fn has_op(&self, v: ValueRef) -> bool {
+        self.ops.contains(&v)
     }
 }
 
@@ -3,36 +3,55 @@ use crate::basicblock::BasicBlock;
 use crate::constant::Constant;
 use crate::context::Context;
 use crate::function::Function;
+use crate::global::Global;
 use crate::indexable::Indexable;
 use crate::instruction::Instruction;
 use crate::value::Value;
 
 use std::fmt;
 
+// Kind id in the top 8 bits, generation of the arena slot in bits 32..55,
+// position in the arena in the low 32 bits
 #[derive(Clone, Copy, Eq, PartialEq, Hash)]
-pub struct RawValueRef(usize);
+pub struct RawValueRef(u64);
+
+const ID_SHIFT: u32 = 56;
+const GEN_SHIFT: u32 = 32;
+pub const GEN_MAX: usize = 0xFF_FFFF;
+const POS_MASK: u64 = 0xFFFF_FFFF;
 
 impl Indexable for RawValueRef {
-    fn to_index(&self) -> usize {
+    fn to_index(&self) -> u64 {
         self.0
     }
 }
 
 impl RawValueRef {
-    pub fn new(x: usize) -> RawValueRef {
+    pub fn new(x: u64) -> RawValueRef {
         RawValueRef(x)
     }
 
     pub fn get_id(&self) -> usize {
-        (self.to_index() & 0xFFFF0000) >> 16
+        (self.to_index() >> ID_SHIFT) as usize
+    }
+
+    pub fn get_gen(&self) -> usize {
+        ((self.to_index() >> GEN_SHIFT) as usize) & GEN_MAX
     }
 
     pub fn get_pos(&self) -> usize {
-        self.to_index() & 0xFFFF
+        (self.to_index() & POS_MASK) as usize
     }
 
-    pub fn make(id: usize, pos: usize) -> RawValueRef {
-        RawValueRef::new((id << 16) | pos)
+    pub fn make(id: usize, gen: usize, pos: usize) -> RawValueRef {
+        if pos as u64 > POS_MASK {
+            panic!(
+                "Too many values of kind {}: position {} overflows the reference encoding",
+                id, pos
+            );
+        }
+        assert!(gen <= GEN_MAX);
+        RawValueRef::new(((id as u64) << ID_SHIFT) | ((gen as u64) << GEN_SHIFT) | pos as u64)
     }
 }
 
@@ -40,9 +59,10 @@ impl fmt::Debug for RawValueRef {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "RawValueRef({}, id={}, pos={})",
+            "RawValueRef({}, id={}, gen={}, pos={})",
             self.0,
             self.get_id(),
+            self.get_gen(),
             self.get_pos()
         )
     }
@@ -54,10 +74,10 @@ pub trait SubValueRef {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct ValueRef(usize);
+pub struct ValueRef(u64);
 
 impl Indexable for ValueRef {
-    fn to_index(&self) -> usize {
+    fn to_index(&self) -> u64 {
         self.0
     }
 }
@@ -89,10 +109,10 @@ impl ValueRef {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct InstructionRef(usize);
+pub struct InstructionRef(u64);
 
 impl Indexable for InstructionRef {
-    fn to_index(&self) -> usize {
+    fn to_index(&self) -> u64 {
         self.0
     }
 }
@@ -125,10 +145,10 @@ impl InstructionRef {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct BasicBlockRef(usize);
+pub struct BasicBlockRef(u64);
 
 impl Indexable for BasicBlockRef {
-    fn to_index(&self) -> usize {
+    fn to_index(&self) -> u64 {
         self.0
     }
 }
@@ -161,10 +181,10 @@ impl BasicBlockRef {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct FunctionRef(usize);
+pub struct FunctionRef(u64);
 
 impl Indexable for FunctionRef {
-    fn to_index(&self) -> usize {
+    fn to_index(&self) -> u64 {
         self.0
     }
 }
@@ -197,10 +217,10 @@ impl FunctionRef {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct ConstantRef(usize);
+pub struct ConstantRef(u64);
 
 impl Indexable for ConstantRef {
-    fn to_index(&self) -> usize {
+    fn to_index(&self) -> u64 {
         self.0
     }
 }
@@ -227,16 +247,17 @@ impl ConstantRef {
         ctx.get_data_constant(*self)
     }
 
+    #[allow(dead_code)]
     pub fn own_mut<'a>(&self, ctx: &'a mut Context) -> Option<&'a mut Constant> {
         ctx.get_data_constant_mut(*self)
     }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct ArgumentRef(usize);
+pub struct ArgumentRef(u64);
 
 impl Indexable for ArgumentRef {
-    fn to_index(&self) -> usize {
+    fn to_index(&self) -> u64 {
         self.0
     }
 }
@@ -268,6 +289,42 @@ impl ArgumentRef {
     }
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct GlobalRef(u64);
+
+impl Indexable for GlobalRef {
+    fn to_index(&self) -> u64 {
+        self.0
+    }
+}
+
+impl SubValueRef for GlobalRef {
+    fn get_raw(&self) -> RawValueRef {
+        RawValueRef::new(self.0)
+    }
+
+    const ID: usize = 6;
+}
+
+impl From<RawValueRef> for GlobalRef {
+    fn from(x: RawValueRef) -> Self {
+        if x.get_id() != Self::ID {
+            panic!("invalid ref {:?}", x);
+        };
+        Self(x.to_index())
+    }
+}
+
+impl GlobalRef {
+    pub fn own<'a>(&self, ctx: &'a Context) -> Option<&'a Global> {
+        ctx.get_data_global(*self)
+    }
+
+    pub fn own_mut<'a>(&self, ctx: &'a mut Context) -> Option<&'a mut Global> {
+        ctx.get_data_global_mut(*self)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum ValueRefEnum {
     Ins(InstructionRef),
@@ -275,10 +332,11 @@ pub enum ValueRefEnum {
     Fun(FunctionRef),
     Const(ConstantRef),
     Arg(ArgumentRef),
+    Global(GlobalRef),
 }
 
 impl ValueRef {
-    pub fn to_enum(&self) -> ValueRefEnum {
+    pub fn to_enum(self) -> ValueRefEnum {
         let raw = RawValueRef::new(self.to_index());
         match raw.get_id() {
             InstructionRef::ID => ValueRefEnum::Ins(raw.into()),
@@ -286,7 +344,34 @@ impl ValueRef {
             FunctionRef::ID => ValueRefEnum::Fun(raw.into()),
             ConstantRef::ID => ValueRefEnum::Const(raw.into()),
             ArgumentRef::ID => ValueRefEnum::Arg(raw.into()),
+            GlobalRef::ID => ValueRefEnum::Global(raw.into()),
             _ => unreachable!(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_ref_large_pos() {
+        let raw = RawValueRef::make(ConstantRef::ID, 0, 70_000);
+        assert_eq!(raw.get_id(), ConstantRef::ID);
+        assert_eq!(raw.get_gen(), 0);
+        assert_eq!(raw.get_pos(), 70_000);
+
+        let raw = RawValueRef::make(InstructionRef::ID, GEN_MAX, POS_MASK as usize);
+        assert_eq!(raw.get_id(), InstructionRef::ID);
+        assert_eq!(raw.get_gen(), GEN_MAX);
+        assert_eq!(raw.get_pos(), POS_MASK as usize);
+    }
+
+    // A position over 32 bits only exists with 64 bits usize
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    #[should_panic(expected = "overflows the reference encoding")]
+    fn raw_ref_overflow() {
+        RawValueRef::make(InstructionRef::ID, 0, POS_MASK as usize + 1);
+    }
+}
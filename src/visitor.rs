@@ -0,0 +1,283 @@
+// Traversal of the IR
+//
+// A `Visitor` walks the defined functions, their blocks and instructions in
+// order, with a callback per kind of object. `visit_ins` dispatches on the
+// opcode; every callback does nothing by default.
+//
+// A `Rewriter` walks the instructions of a function with mutable access to
+// the context, and tells for each one whether to keep, replace or delete it.
+// The instructions of a block are listed before visiting it: the ones
+// inserted by the callback are not visited, and the ones it erased or moved
+// out of the block are skipped.
+
+use crate::basicblock::BasicBlock;
+use crate::context::Context;
+use crate::function::Function;
+use crate::instruction::Instruction;
+use crate::isa::Opcode;
+use crate::valueref::{FunctionRef, InstructionRef, ValueRef};
+
+pub trait Visitor {
+    fn visit_fun(&mut self, _ctx: &Context, _fun: &Function) {}
+
+    // After all the blocks of `fun`
+    fn visit_fun_end(&mut self, _ctx: &Context, _fun: &Function) {}
+
+    fn visit_bb(&mut self, _ctx: &Context, _bb: &BasicBlock) {}
+
+    fn visit_ins(&mut self, ctx: &Context, ins: &Instruction) {
+        match ins.opcode() {
+            Opcode::Phi => self.visit_phi(ctx, ins),
+            Opcode::Call => self.visit_call(ctx, ins),
+            Opcode::Load => self.visit_load(ctx, ins),
+            Opcode::Store => self.visit_store(ctx, ins),
+            _ if ins.infos().is_term() => self.visit_term(ctx, ins),
+            _ => self.visit_op(ctx, ins),
+        }
+    }
+
+    fn visit_phi(&mut self, _ctx: &Context, _ins: &Instruction) {}
+
+    fn visit_call(&mut self, _ctx: &Context, _ins: &Instruction) {}
+
+    fn visit_load(&mut self, _ctx: &Context, _ins: &Instruction) {}
+
+    fn visit_store(&mut self, _ctx: &Context, _ins: &Instruction) {}
+
+    // `ret`, `b` and `bc`
+    fn visit_term(&mut self, _ctx: &Context, _ins: &Instruction) {}
+
+    // Any other instruction: arithmetic, comparisons, `select`, `alloca`, `offset`
+    fn visit_op(&mut self, _ctx: &Context, _ins: &Instruction) {}
+}
+
+// Visit every function defined in `ctx`
+pub fn walk<V: Visitor>(ctx: &Context, v: &mut V) {
+    for fun in ctx.funs() {
+        if !fun.own(ctx).unwrap().is_decl() {
+            walk_fun(ctx, fun, v);
+        }
+    }
+}
+
+pub fn walk_fun<V: Visitor>(ctx: &Context, fun: FunctionRef, v: &mut V) {
+    let fun = fun.own(ctx).unwrap();
+    v.visit_fun(ctx, fun);
+    for bb in fun.bbs() {
+        let bb = bb.own(ctx).unwrap();
+        v.visit_bb(ctx, bb);
+        for ins in bb.ins() {
+            v.visit_ins(ctx, ins.own(ctx).unwrap());
+        }
+    }
+    v.visit_fun_end(ctx, fun);
+}
+
+// What to do with the instruction given to `Rewriter::rewrite`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Action {
+    Keep,
+    // Replace all uses by the value, then erase the instruction
+    Replace(ValueRef),
+    // Erase the instruction, panics if it still has uses
+    Delete,
+}
+
+pub trait Rewriter {
+    fn rewrite(&mut self, ctx: &mut Context, ins: InstructionRef) -> Action;
+}
+
+// Rewrite every instruction of `fun`, in order
+// Returns true if an instruction was replaced or deleted
+pub fn rewrite_fun<R: Rewriter>(ctx: &mut Context, fun: FunctionRef, r: &mut R) -> bool {
+    let mut changed = false;
+    let bbs = fun.own(ctx).unwrap().bbs().to_vec();
+    for bb in bbs {
+        let ins_list = bb.own(ctx).unwrap().ins().to_vec();
+        for ins in ins_list {
+            let is_in_bb = ins.own(ctx).is_some_and(|ins| ins.parent() == Some(bb));
+            if !is_in_bb {
+                continue;
+            }
+
+            match r.rewrite(ctx, ins) {
+                Action::Keep => {}
+                Action::Replace(val) if val == ins.into() => {}
+                Action::Replace(val) => {
+                    ctx.replace_all_uses_with(ins.into(), val);
+                    ctx.erase_ins(ins);
+                    changed = true;
+                }
+                Action::Delete => {
+                    ctx.erase_ins(ins);
+                    changed = true;
+                }
+            }
+        }
+    }
+    changed
+}
+
+// Rewrite every function defined in `ctx`
+#[allow(dead_code)]
+pub fn rewrite<R: Rewriter>(ctx: &mut Context, r: &mut R) -> bool {
+    let funs: Vec<FunctionRef> = ctx.funs().collect();
+    let mut changed = false;
+    for fun in funs {
+        if !fun.own(ctx).unwrap().is_decl() {
+            changed |= rewrite_fun(ctx, fun, r);
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker;
+    use crate::gop;
+    use crate::loader;
+    use crate::test_utils::find_path;
+    use crate::valueref::ValueRefEnum;
+
+    fn load_file(path: &str) -> Context {
+        let path = find_path(path);
+        let mut ctx = Context::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse(&path));
+        ctx
+    }
+
+    // Number of calls of each callback
+    #[derive(Default)]
+    struct Counter {
+        funs: usize,
+        bbs: usize,
+        phis: usize,
+        calls: usize,
+        terms: usize,
+        ops: usize,
+    }
+
+    impl Visitor for Counter {
+        fn visit_fun(&mut self, _ctx: &Context, _fun: &Function) {
+            self.funs += 1;
+        }
+
+        fn visit_bb(&mut self, _ctx: &Context, _bb: &BasicBlock) {
+            self.bbs += 1;
+        }
+
+        fn visit_phi(&mut self, _ctx: &Context, _ins: &Instruction) {
+            self.phis += 1;
+        }
+
+        fn visit_call(&mut self, _ctx: &Context, _ins: &Instruction) {
+            self.calls += 1;
+        }
+
+        fn visit_term(&mut self, _ctx: &Context, _ins: &Instruction) {
+            self.terms += 1;
+        }
+
+        fn visit_op(&mut self, _ctx: &Context, _ins: &Instruction) {
+            self.ops += 1;
+        }
+    }
+
+    #[test]
+    fn visit_fact_iter() {
+        let ctx = load_file("examples/fact_iter.ir");
+        let mut counter = Counter::default();
+        walk(&ctx, &mut counter);
+        assert_eq!(counter.funs, 2);
+        assert_eq!(counter.bbs, 6);
+        assert_eq!(counter.phis, 4);
+        assert_eq!(counter.calls, 3);
+        assert_eq!(counter.terms, 6);
+        assert_eq!(counter.ops, 6);
+    }
+
+    // `add x, 0` is replaced by x, `mul x, 0` by 0
+    // The instruction after a `neg` is erased by the callback if unused
+    struct Simplify;
+
+    impl Rewriter for Simplify {
+        fn rewrite(&mut self, ctx: &mut Context, ins: InstructionRef) -> Action {
+            let ins_obj = ins.own(ctx).unwrap();
+            let ops = ins_obj.val().ops().to_vec();
+            let is_zero = |v: ValueRef| match v.to_enum() {
+                ValueRefEnum::Const(c) => c.own(ctx).unwrap().const_int() == 0,
+                _ => false,
+            };
+            match ins_obj.opcode() {
+                Opcode::Add if is_zero(ops[1]) => Action::Replace(ops[0]),
+                Opcode::Mul if is_zero(ops[1]) => Action::Replace(ops[1]),
+                Opcode::Neg => {
+                    let bb = ins_obj.parent().unwrap().own(ctx).unwrap();
+                    let pos = bb.ins().iter().position(|x| *x == ins).unwrap();
+                    let next = bb.ins()[pos + 1];
+                    let next_val = next.own(ctx).unwrap().val();
+                    if next_val.is_def() && !next_val.has_uses() {
+                        ctx.erase_ins(next);
+                    }
+                    Action::Keep
+                }
+                _ => Action::Keep,
+            }
+        }
+    }
+
+    #[test]
+    fn rewrite_simplify() {
+        let mut ctx = Context::new();
+        let gmod = gop::Module::parse_str(
+            "rewrite.ir",
+            "f:\n.fun i64, %x\n\nB0:\n\tadd %a, %x, 0\n\tmul %b, %a, 0\n\tneg %c, %x\n\tadd %d, %x, 1\n\tadd %e, %a, %b\n\tret %e\n",
+        );
+        loader::load_gop(&mut ctx, &gmod);
+
+        // %e is `add %x, 0` once %a and %b are replaced, %d is erased while iterating
+        assert!(rewrite(&mut ctx, &mut Simplify));
+        checker::check_code(&ctx);
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("\tneg %c, %x\n\tret %x\n"));
+        assert!(!rewrite(&mut ctx, &mut Simplify));
+    }
+
+    // Every `neg` is deleted
+    struct DeleteNeg;
+
+    impl Rewriter for DeleteNeg {
+        fn rewrite(&mut self, ctx: &mut Context, ins: InstructionRef) -> Action {
+            match ins.own(ctx).unwrap().opcode() {
+                Opcode::Neg => Action::Delete,
+                _ => Action::Keep,
+            }
+        }
+    }
+
+    fn load_str(src: &str) -> Context {
+        let mut ctx = Context::new();
+        loader::load_gop(&mut ctx, &gop::Module::parse_str("delete.ir", src));
+        ctx
+    }
+
+    #[test]
+    fn rewrite_delete() {
+        let mut ctx =
+            load_str("f:\n.fun i64, %x\n\nB0:\n\tneg %a, %x\n\tadd %b, %x, 1\n\tret %b\n");
+        assert!(rewrite(&mut ctx, &mut DeleteNeg));
+        checker::check_code(&ctx);
+        let out = format!("{}", loader::build_gop(&ctx));
+        assert!(out.contains("B0:\n\tadd %b, %x, 1\n\tret %b\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Erase instruction a still used by b")]
+    fn rewrite_delete_used() {
+        let mut ctx =
+            load_str("f:\n.fun i64, %x\n\nB0:\n\tneg %a, %x\n\tadd %b, %a, 1\n\tret %b\n");
+        rewrite(&mut ctx, &mut DeleteNeg);
+    }
+}